@@ -0,0 +1,802 @@
+use flate2::write;
+use reqwest::{Response, StatusCode};
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use serde::de::DeserializeOwned;
+use std::{
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    asset_response::{AssetResponse, AssetResponseV1},
+    endpoints::RobloxEndpoints,
+    open_cloud_asset_response::OpenCloudAssetResponse,
+    throttle::Throttle,
+};
+
+/// How asset downloads are authenticated
+#[derive(Debug, Clone, Default)]
+pub enum AssetAuth {
+    /// The .ROBLOSECURITY cookie held by the client's cookie jar
+    #[default]
+    Cookie,
+    /// An Open Cloud API key, sent to the Open Cloud asset delivery endpoints
+    ApiKey(String),
+}
+
+impl AssetAuth {
+    fn describe(&self) -> &'static str {
+        match self {
+            AssetAuth::Cookie => "the .ROBLOSECURITY cookie",
+            AssetAuth::ApiKey(_) => {
+                "the API key (it needs the legacy-asset:manage scope and access to the asset)"
+            }
+        }
+    }
+}
+
+/// Every binary place (.rbxl) and model (.rbxm) file starts with this
+const ROBLOX_BINARY_MAGIC: &[u8] = b"<roblox!";
+
+/// How assets are looked up and downloaded
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Try every location in order when none of them is explicitly in the `source` format
+    pub lenient_format: bool,
+    pub auth: AssetAuth,
+    /// Overrides the client's timeout for the CDN download itself, which can take
+    /// much longer than the metadata lookup for big places
+    pub download_timeout: Option<Duration>,
+    pub endpoints: RobloxEndpoints,
+    /// How many more times to send a metadata request whose JSON body doesn't
+    /// parse, which is usually a connection dropped partway through the body
+    pub parse_retries: u32,
+    /// Shared limit on how fast CDN downloads go, if any
+    pub throttle: Option<Arc<Throttle>>,
+    /// Decompressed downloads bigger than this many bytes are staged in a
+    /// temp file instead of memory
+    pub spill_threshold: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    MetadataFetch(reqwest_middleware::Error),
+    Unauthorized {
+        status: StatusCode,
+        auth: &'static str,
+    },
+    MetadataParse(anyhow::Error),
+    /// The asset doesn't exist (any more)
+    NotFound {
+        status: StatusCode,
+    },
+    NoSourceLocation {
+        formats: Vec<String>,
+    },
+    CdnFetch {
+        url: String,
+        source: reqwest_middleware::Error,
+    },
+    CdnStatus {
+        url: String,
+        status: StatusCode,
+    },
+    Decompress(anyhow::Error),
+    NotRobloxBinary {
+        first_bytes: Vec<u8>,
+    },
+    /// Looks like a Roblox binary file, but doesn't parse as one
+    Unparseable(String),
+    LocalRead {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::MetadataFetch(e) => write!(f, "failed to GET asset metadata: {}", e),
+            AssetError::MetadataParse(e) => write!(f, "failed to parse asset metadata: {}", e),
+            AssetError::NotFound { status } => {
+                write!(f, "HTTP {}, the asset doesn't exist or was deleted", status)
+            }
+            AssetError::Unauthorized { status, auth } => {
+                write!(
+                    f,
+                    "HTTP {}, not allowed to download it with {}",
+                    status, auth
+                )
+            }
+            AssetError::NoSourceLocation { formats } if formats.is_empty() => {
+                write!(f, "asset metadata has no locations")
+            }
+            AssetError::NoSourceLocation { formats } => write!(
+                f,
+                "no CDN source location in asset metadata (formats seen: {}; try --lenient-format)",
+                formats.join(", ")
+            ),
+            AssetError::CdnFetch { url, source } => {
+                write!(f, "failed to GET CDN {}: {}", url, source)
+            }
+            AssetError::CdnStatus { url, status } => {
+                write!(f, "CDN {} returned HTTP {}", url, status)
+            }
+            AssetError::Decompress(e) => write!(f, "failed to decompress: {}", e),
+            AssetError::NotRobloxBinary { first_bytes } => write!(
+                f,
+                "returned non-rbx content (first bytes: {})",
+                first_bytes.escape_ascii()
+            ),
+            AssetError::Unparseable(e) => write!(f, "downloaded file doesn't parse: {}", e),
+            AssetError::LocalRead { path, source } => {
+                write!(f, "failed to read {}: {}", path, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+impl AssetError {
+    /// Short classification of the failure, for summaries
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AssetError::MetadataFetch(_) => "metadata fetch failed",
+            AssetError::MetadataParse(_) => "metadata parse failed",
+            AssetError::NotFound { .. } => "not found",
+            AssetError::Unauthorized { .. } => "not authorized",
+            AssetError::NoSourceLocation { .. } => "no source location",
+            AssetError::CdnFetch { .. } => "CDN fetch failed",
+            AssetError::CdnStatus { .. } => "CDN returned an error",
+            AssetError::Decompress(_) => "decompress failed",
+            AssetError::NotRobloxBinary { .. } => "non-rbx content",
+            AssetError::Unparseable(_) => "doesn't parse",
+            AssetError::LocalRead { .. } => "local file read failed",
+        }
+    }
+}
+
+// Holds decompressed bytes in memory until there are more than the threshold,
+// then moves them to a temp file. A growing Vec can briefly need twice its size
+// while it reallocates, the file is read back in one exactly sized allocation
+struct SpillBuffer {
+    memory: Vec<u8>,
+    threshold: Option<usize>,
+    file: Option<(std::fs::File, PathBuf)>,
+}
+
+static SPILL_FILES: AtomicU64 = AtomicU64::new(0);
+
+impl SpillBuffer {
+    fn new(threshold: Option<usize>) -> Self {
+        SpillBuffer {
+            memory: Vec::new(),
+            threshold,
+            file: None,
+        }
+    }
+
+    fn into_bytes(mut self) -> io::Result<Vec<u8>> {
+        match self.file.take() {
+            Some((file, path)) => {
+                drop(file);
+                let bytes = std::fs::read(&path);
+                let _ = std::fs::remove_file(&path);
+                bytes
+            }
+            None => Ok(std::mem::take(&mut self.memory)),
+        }
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some((file, _)) = &mut self.file {
+            return file.write(buf);
+        }
+
+        self.memory.extend_from_slice(buf);
+        if self
+            .threshold
+            .is_some_and(|threshold| self.memory.len() > threshold)
+        {
+            let path = std::env::temp_dir().join(format!(
+                "roblox-packagelink-updater-{}-{}.part",
+                std::process::id(),
+                SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = std::fs::File::create(&path)?;
+            file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some((file, path));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// An abandoned download doesn't leave its temp file behind
+impl Drop for SpillBuffer {
+    fn drop(&mut self) {
+        if let Some((file, path)) = self.file.take() {
+            drop(file);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+enum Decoder {
+    Gzip(write::GzDecoder<SpillBuffer>),
+    Zlib(write::ZlibDecoder<SpillBuffer>),
+    Deflate(write::DeflateDecoder<SpillBuffer>),
+    Brotli(Box<brotli::DecompressorWriter<SpillBuffer>>),
+    Identity(SpillBuffer),
+}
+
+impl Decoder {
+    // The first chunk is needed to tell which kind of deflate it is
+    fn new(encoding: Option<&str>, first_chunk: &[u8], spill_threshold: Option<usize>) -> Self {
+        let sink = SpillBuffer::new(spill_threshold);
+        match encoding {
+            Some("gzip") => Decoder::Gzip(write::GzDecoder::new(sink)),
+            // Servers disagree on whether deflate means zlib-wrapped or raw, so
+            // look for a zlib header
+            Some("deflate") if is_zlib_header(first_chunk) => {
+                Decoder::Zlib(write::ZlibDecoder::new(sink))
+            }
+            Some("deflate") => Decoder::Deflate(write::DeflateDecoder::new(sink)),
+            Some("br") => Decoder::Brotli(Box::new(brotli::DecompressorWriter::new(sink, 4096))),
+            None | Some("identity") => Decoder::Identity(sink),
+            Some(other) => {
+                tracing::warn!(
+                    "Unrecognized Content-Encoding '{}', using the response body as-is",
+                    other
+                );
+                Decoder::Identity(sink)
+            }
+        }
+    }
+
+    fn write_all(&mut self, chunk: &[u8]) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.write_all(chunk),
+            Decoder::Zlib(decoder) => decoder.write_all(chunk),
+            Decoder::Deflate(decoder) => decoder.write_all(chunk),
+            Decoder::Brotli(decoder) => decoder.write_all(chunk),
+            Decoder::Identity(sink) => sink.write_all(chunk),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        let sink = match self {
+            Decoder::Gzip(decoder) => decoder.finish()?,
+            Decoder::Zlib(decoder) => decoder.finish()?,
+            Decoder::Deflate(decoder) => decoder.finish()?,
+            Decoder::Brotli(decoder) => decoder.into_inner().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "truncated brotli stream")
+            })?,
+            Decoder::Identity(sink) => sink,
+        };
+        sink.into_bytes()
+    }
+}
+
+// RFC 1950: deflate with a window of at most 32K, and a header checksum
+fn is_zlib_header(bytes: &[u8]) -> bool {
+    match bytes {
+        [cmf, flg, ..] => {
+            cmf & 0x0f == 8 && cmf >> 4 <= 7 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31)
+        }
+        _ => false,
+    }
+}
+
+// Most that a response's Content-Length can reserve before any of it arrives
+const MAX_PREALLOCATION: usize = 64 * 1024 * 1024;
+
+// Decompresses the body as it arrives, so the compressed and decompressed
+// copies are never in memory in full at the same time
+async fn decompress_if_needed(
+    mut binary_response: Response,
+    throttle: Option<&Throttle>,
+    spill_threshold: Option<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    // weird bug reqwest wouldn't decompress it so i had to add this
+    let encoding = binary_response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.trim().to_ascii_lowercase());
+
+    // Nothing to decompress, so the body goes straight into one buffer
+    if matches!(encoding.as_deref(), None | Some("identity")) {
+        // Content-Length is only the server's word for it, so don't let it
+        // size more than a bounded allocation up front
+        let capacity = binary_response
+            .content_length()
+            .map_or(0, |length| usize::try_from(length).unwrap_or(usize::MAX))
+            .min(
+                spill_threshold
+                    .unwrap_or(MAX_PREALLOCATION)
+                    .min(MAX_PREALLOCATION),
+            );
+        let mut body_bytes = Vec::with_capacity(capacity);
+        while let Some(chunk) = binary_response.chunk().await? {
+            if let Some(throttle) = throttle {
+                throttle.consume(chunk.len()).await;
+            }
+            body_bytes.extend_from_slice(&chunk);
+        }
+        return Ok(body_bytes);
+    }
+
+    // Read chunk by chunk so a throttle can hold each one back
+    let mut decoder: Option<Decoder> = None;
+    while let Some(chunk) = binary_response.chunk().await? {
+        if let Some(throttle) = throttle {
+            throttle.consume(chunk.len()).await;
+        }
+        decoder
+            .get_or_insert_with(|| Decoder::new(encoding.as_deref(), &chunk, spill_threshold))
+            .write_all(&chunk)?;
+    }
+
+    match decoder {
+        Some(decoder) => Ok(decoder.finish()?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// What [`send_for_json`] got back
+pub(crate) enum JsonResponse<T> {
+    Parsed(T),
+    /// A non-success status, with the start of the body
+    Status(StatusCode, String),
+}
+
+/// Why [`send_for_json`] didn't get a response
+#[derive(Debug)]
+pub(crate) enum JsonError {
+    Send(reqwest_middleware::Error),
+    Parse(anyhow::Error),
+}
+
+/// Sends the request and parses its JSON body. A connection dropped partway
+/// through the body still comes back as a 200, which the retry middleware never
+/// sees, so a body that doesn't read or parse sends the whole request again, up
+/// to `retries` more times. Non-success responses are handed back unparsed.
+pub(crate) async fn send_for_json<T: DeserializeOwned>(
+    request: RequestBuilder,
+    retries: u32,
+) -> Result<JsonResponse<T>, JsonError> {
+    let mut attempt = 0;
+    loop {
+        // Nothing in these requests is streamed, so they can always be cloned
+        let response = request
+            .try_clone()
+            .expect("request has no streamed body")
+            .send()
+            .await
+            .map_err(JsonError::Send)?;
+        let status = response.status();
+        let url = response.url().clone();
+
+        let parsed = match response.bytes().await {
+            Ok(body) if !status.is_success() => {
+                return Ok(JsonResponse::Status(
+                    status,
+                    String::from_utf8_lossy(&body).chars().take(200).collect(),
+                ));
+            }
+            Ok(body) => serde_json::from_slice::<T>(&body).map_err(|e| {
+                anyhow::anyhow!(
+                    "{} (body: {})",
+                    e,
+                    String::from_utf8_lossy(&body)
+                        .chars()
+                        .take(200)
+                        .collect::<String>()
+                )
+            }),
+            Err(e) => Err(anyhow::Error::new(e).context("failed to read the body")),
+        };
+
+        match parsed {
+            Ok(parsed) => return Ok(JsonResponse::Parsed(parsed)),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!(
+                    "Couldn't parse the response from {}, retrying ({}/{}): {}",
+                    url,
+                    attempt,
+                    retries,
+                    e
+                );
+            }
+            Err(e) => return Err(JsonError::Parse(e)),
+        }
+    }
+}
+
+/// A downloaded asset, and whether its CDN URL had expired and had to be
+/// looked up again before the download went through
+pub struct FetchedAsset {
+    pub bytes: Vec<u8>,
+    pub cdn_refreshed: bool,
+    /// Which of the asset's source locations the download came from, 0 being
+    /// the first one assetdelivery listed
+    pub location_index: usize,
+}
+
+fn parse_metadata<T>(
+    response: Result<JsonResponse<T>, JsonError>,
+    auth: &AssetAuth,
+) -> Result<T, AssetError> {
+    match response {
+        Ok(JsonResponse::Parsed(parsed)) => Ok(parsed),
+        Ok(JsonResponse::Status(status, _))
+            if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
+        {
+            Err(AssetError::Unauthorized {
+                status,
+                auth: auth.describe(),
+            })
+        }
+        Ok(JsonResponse::Status(status, _))
+            if status == StatusCode::NOT_FOUND || status == StatusCode::GONE =>
+        {
+            Err(AssetError::NotFound { status })
+        }
+        Ok(JsonResponse::Status(status, body)) => Err(AssetError::MetadataParse(anyhow::anyhow!(
+            "HTTP {}: {}",
+            status,
+            body
+        ))),
+        Err(JsonError::Send(e)) => Err(AssetError::MetadataFetch(e)),
+        Err(JsonError::Parse(e)) => Err(AssetError::MetadataParse(e)),
+    }
+}
+
+/// Looks up every CDN location the asset can be downloaded from, in the order
+/// they should be tried
+async fn fetch_cdn_locations(
+    client: &ClientWithMiddleware,
+    asset_id: &str,
+    version: Option<u64>,
+    options: &FetchOptions,
+) -> Result<Vec<String>, AssetError> {
+    let auth = &options.auth;
+    let request = match auth {
+        AssetAuth::Cookie => {
+            let asset_url = match version {
+                Some(version) => format!(
+                    "{}/v2/asset/?id={}&version={}",
+                    options.endpoints.assetdelivery, asset_id, version
+                ),
+                None => format!(
+                    "{}/v2/asset/?id={}",
+                    options.endpoints.assetdelivery, asset_id
+                ),
+            };
+            client.get(asset_url)
+        }
+        AssetAuth::ApiKey(api_key) => {
+            let asset_url = match version {
+                Some(version) => format!(
+                    "{}/asset-delivery-api/v1/assetId/{}/version/{}",
+                    options.endpoints.apis, asset_id, version
+                ),
+                None => format!(
+                    "{}/asset-delivery-api/v1/assetId/{}",
+                    options.endpoints.apis, asset_id
+                ),
+            };
+            client.get(asset_url).header("x-api-key", api_key)
+        }
+    };
+
+    // Open Cloud only ever hands out the one location
+    if let AssetAuth::ApiKey(_) = auth {
+        let asset_meta: OpenCloudAssetResponse =
+            parse_metadata(send_for_json(request, options.parse_retries).await, auth)?;
+        return Ok(vec![asset_meta.location().clone()]);
+    }
+
+    let asset_meta: AssetResponse =
+        parse_metadata(send_for_json(request, options.parse_retries).await, auth)?;
+
+    // Some of the source locations can be partial or regional copies, so all
+    // of them are kept to fall back on
+    let locations = asset_meta.locations();
+    let mut source_locations: Vec<String> = locations
+        .iter()
+        .filter(|location| location.asset_format() == "source")
+        .map(|location| location.location().clone())
+        .collect();
+    if source_locations.is_empty() && options.lenient_format {
+        source_locations = locations
+            .iter()
+            .map(|location| location.location().clone())
+            .collect();
+    }
+
+    if source_locations.is_empty() {
+        // v1 sometimes still has a location for assets v2 lists none for
+        match fetch_v1_location(client, asset_id, version, options).await {
+            Ok(location) => {
+                tracing::info!(
+                    "Asset {} is served by assetdelivery v1, v2 listed no source location",
+                    asset_id
+                );
+                return Ok(vec![location]);
+            }
+            Err(e) => {
+                tracing::debug!(
+                    "assetdelivery v1 has no location for asset {}: {}",
+                    asset_id,
+                    e
+                );
+            }
+        }
+        return Err(AssetError::NoSourceLocation {
+            formats: locations
+                .iter()
+                .map(|location| location.asset_format().clone())
+                .collect(),
+        });
+    }
+    tracing::debug!("Asset {} is served by assetdelivery v2", asset_id);
+    Ok(source_locations)
+}
+
+// The location the older v1 endpoint gives for the asset, used when v2 has no
+// source location
+async fn fetch_v1_location(
+    client: &ClientWithMiddleware,
+    asset_id: &str,
+    version: Option<u64>,
+    options: &FetchOptions,
+) -> Result<String, AssetError> {
+    let asset_url = match version {
+        Some(version) => format!(
+            "{}/v1/assetId/{}/version/{}",
+            options.endpoints.assetdelivery, asset_id, version
+        ),
+        None => format!(
+            "{}/v1/assetId/{}",
+            options.endpoints.assetdelivery, asset_id
+        ),
+    };
+    let asset_meta: AssetResponseV1 = parse_metadata(
+        send_for_json(client.get(asset_url), options.parse_retries).await,
+        &options.auth,
+    )?;
+    Ok(asset_meta.location().clone())
+}
+
+async fn fetch_cdn(
+    client: &ClientWithMiddleware,
+    cdn: &str,
+    options: &FetchOptions,
+) -> Result<Response, AssetError> {
+    let mut request = client.get(cdn);
+    if let Some(download_timeout) = options.download_timeout {
+        request = request.timeout(download_timeout);
+    }
+    request.send().await.map_err(|source| AssetError::CdnFetch {
+        url: cdn.to_string(),
+        source,
+    })
+}
+
+/// Downloads one CDN location and checks that it really is a Roblox binary file
+async fn download_location(
+    client: &ClientWithMiddleware,
+    cdn: &str,
+    options: &FetchOptions,
+) -> Result<Vec<u8>, AssetError> {
+    let binary_response = fetch_cdn(client, cdn, options).await?;
+
+    // A location can redirect to another host, which only gets the cookie if the
+    // jar has it for that host as well. Logged so a refusal there can be traced
+    let requested_host = reqwest::Url::parse(cdn)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string));
+    let final_host = binary_response.url().host_str();
+    if let Some(final_host) = final_host
+        && requested_host.as_deref() != Some(final_host)
+    {
+        if binary_response.status().is_success() {
+            tracing::debug!("Download of {} was redirected to {}", cdn, final_host);
+        } else {
+            tracing::warn!(
+                "Download of {} was redirected to {}, which answered HTTP {}",
+                cdn,
+                final_host,
+                binary_response.status()
+            );
+        }
+    }
+
+    if !binary_response.status().is_success() {
+        return Err(AssetError::CdnStatus {
+            url: cdn.to_string(),
+            status: binary_response.status(),
+        });
+    }
+
+    let bytes = decompress_if_needed(
+        binary_response,
+        options.throttle.as_deref(),
+        options.spill_threshold,
+    )
+    .await
+    .map_err(AssetError::Decompress)?;
+
+    // An HTML error page or empty body would otherwise only fail later as a
+    // confusing parse error
+    if !bytes.starts_with(ROBLOX_BINARY_MAGIC) {
+        return Err(AssetError::NotRobloxBinary {
+            first_bytes: bytes.iter().take(32).copied().collect(),
+        });
+    }
+
+    Ok(bytes)
+}
+
+/// Reads an asset that was downloaded ahead of time, checking it the same way
+/// a download would be.
+pub async fn read_local_asset(path: &Path) -> Result<Vec<u8>, AssetError> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|source| AssetError::LocalRead {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+    if !bytes.starts_with(ROBLOX_BINARY_MAGIC) {
+        return Err(AssetError::NotRobloxBinary {
+            first_bytes: bytes.iter().take(32).copied().collect(),
+        });
+    }
+
+    Ok(bytes)
+}
+
+// Parses the download on the blocking pool, handing the bytes back if it works
+async fn check_parses(bytes: Vec<u8>) -> Result<Vec<u8>, AssetError> {
+    tokio::task::spawn_blocking(move || {
+        rbx_binary::from_reader(io::Cursor::new(&bytes))
+            .map(|_| ())
+            .map_err(|e| AssetError::Unparseable(e.to_string()))?;
+        Ok(bytes)
+    })
+    .await
+    .map_err(|e| AssetError::Unparseable(e.to_string()))?
+}
+
+/// Looks up an asset's source location on assetdelivery and downloads it from
+/// the CDN. Fetches `version` when given, otherwise the latest version.
+///
+/// See [`FetchOptions`] for how the lookup and download can be tuned.
+pub async fn fetch_asset_bytes(
+    client: &ClientWithMiddleware,
+    asset_id: &str,
+    version: Option<u64>,
+    options: &FetchOptions,
+) -> Result<Vec<u8>, AssetError> {
+    fetch_asset(client, asset_id, version, options)
+        .await
+        .map(|asset| asset.bytes)
+}
+
+/// Same as [`fetch_asset_bytes`], but also reports whether the CDN URL expired
+/// (HTTP 403) and the download was retried once with a freshly looked up URL,
+/// and which source location the download came from.
+///
+/// When assetdelivery lists several source locations they're tried in order,
+/// moving on to the next one whenever a download fails, isn't a Roblox binary
+/// file or doesn't parse. The last location's error is returned if none of
+/// them work. The last location's download isn't parsed here, that's left to
+/// the caller like for assets with only one location.
+pub async fn fetch_asset(
+    client: &ClientWithMiddleware,
+    asset_id: &str,
+    version: Option<u64>,
+    options: &FetchOptions,
+) -> Result<FetchedAsset, AssetError> {
+    let mut locations = fetch_cdn_locations(client, asset_id, version, options).await?;
+    let mut cdn_refreshed = false;
+    let mut last_error = None;
+    let mut location_index = 0;
+
+    while let Some(cdn) = locations.get(location_index) {
+        // A truncated copy on one location can be fine on another, but parsing
+        // is only worth it while there's another location to try
+        let downloaded = match download_location(client, cdn, options).await {
+            Ok(bytes) if location_index + 1 < locations.len() => check_parses(bytes).await,
+            downloaded => downloaded,
+        };
+        match downloaded {
+            Ok(bytes) => {
+                return Ok(FetchedAsset {
+                    bytes,
+                    cdn_refreshed,
+                    location_index,
+                });
+            }
+            // CDN URLs are signed and can expire before we get to them
+            Err(AssetError::CdnStatus {
+                status: StatusCode::FORBIDDEN,
+                ..
+            }) if !cdn_refreshed => {
+                cdn_refreshed = true;
+                locations = fetch_cdn_locations(client, asset_id, version, options).await?;
+            }
+            Err(e) => {
+                if locations.len() > 1 {
+                    tracing::debug!(
+                        "Source location {} of asset {} failed: {}",
+                        location_index,
+                        asset_id,
+                        e
+                    );
+                }
+                last_error = Some(e);
+                location_index += 1;
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or(AssetError::NoSourceLocation {
+        formats: Vec::new(),
+    }))
+}
+
+/// Looks up an asset's CDN location like [`fetch_asset_bytes`], but only asks
+/// the CDN for the download size. None when the CDN doesn't say.
+pub async fn fetch_asset_size(
+    client: &ClientWithMiddleware,
+    asset_id: &str,
+    version: Option<u64>,
+    options: &FetchOptions,
+) -> Result<Option<u64>, AssetError> {
+    // Only the first location is asked, the others are just fallbacks
+    let cdn = fetch_cdn_locations(client, asset_id, version, options)
+        .await?
+        .swap_remove(0);
+    let response = client
+        .head(&cdn)
+        .send()
+        .await
+        .map_err(|source| AssetError::CdnFetch {
+            url: cdn.clone(),
+            source,
+        })?;
+
+    if !response.status().is_success() {
+        return Err(AssetError::CdnStatus {
+            url: cdn,
+            status: response.status(),
+        });
+    }
+
+    Ok(response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok()))
+}
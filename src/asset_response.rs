@@ -35,3 +35,15 @@ pub struct AssetMetadata {
     #[allow(dead_code)]
     pub value: String,
 }
+
+/// What the older `/v1/assetId/{id}` endpoint answers with: a single location,
+/// with no format to go by
+#[derive(Debug, Deserialize, Getters, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AssetResponseV1 {
+    pub location: String,
+    #[allow(dead_code)]
+    #[serde(default)]
+    pub request_id: String,
+}
@@ -0,0 +1,71 @@
+use std::{fmt, future::Future, time::Duration};
+use tokio::{sync::watch, time::Instant};
+
+/// Why a phase was cut short
+#[derive(Debug, Clone)]
+pub enum Interruption {
+    CtrlC,
+    Timeout(Duration),
+    /// --fail-fast saw this error
+    FailFast(String),
+}
+
+impl fmt::Display for Interruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interruption::CtrlC => write!(f, "Interrupted by Ctrl-C"),
+            Interruption::Timeout(timeout) => {
+                write!(f, "Timed out after {}s", timeout.as_secs())
+            }
+            Interruption::FailFast(error) => {
+                write!(f, "Stopped at the first error (--fail-fast): {}", error)
+            }
+        }
+    }
+}
+
+// Resolves once an error has been sent on `first_error`, never if the sender is
+// dropped without sending one
+async fn wait_for_error(mut first_error: watch::Receiver<Option<String>>) -> String {
+    loop {
+        if let Some(error) = first_error.borrow_and_update().clone() {
+            return error;
+        }
+        if first_error.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Runs `work` to completion unless Ctrl-C is pressed, `timeout` (measured
+/// from `started`) passes or an error arrives on `first_error` first, in which
+/// case `work` is dropped, cancelling whatever it had in flight.
+pub async fn until_interrupted<F: Future>(
+    work: F,
+    started: Instant,
+    timeout: Option<Duration>,
+    first_error: &watch::Receiver<Option<String>>,
+) -> Result<F::Output, Interruption> {
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep_until(started + timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        output = work => Ok(output),
+        _ = tokio::signal::ctrl_c() => Err(Interruption::CtrlC),
+        _ = deadline => Err(Interruption::Timeout(timeout.unwrap_or_default())),
+        error = wait_for_error(first_error.clone()) => Err(Interruption::FailFast(error)),
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it into place, so an
+/// interrupted write never leaves a truncated file at `path`.
+pub async fn write_atomically(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    tokio::fs::write(&temp_path, contents).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
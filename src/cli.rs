@@ -0,0 +1,234 @@
+use anyhow::{Context, Result, bail};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use roblox_packagelink_updater::Config;
+use std::{ffi::OsString, path::PathBuf};
+
+#[derive(Debug, Clone, Parser)]
+#[command(
+    version,
+    about = "Updates every PackageLink in a Roblox universe and republishes its places"
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML file with defaults for any of these options, keyed by their long
+    /// name. Flags and environment variables still take precedence
+    #[arg(long = "config", value_name = "PATH")]
+    pub config_file: Option<PathBuf>,
+
+    /// Universe to update. Prompted for when neither this nor --place-id is given.
+    /// Can be given several times (or comma separated) with --report-unused-packages
+    #[arg(long, env = "RBXL_UNIVERSE_ID", value_delimiter = ',')]
+    pub universe_id: Vec<u64>,
+
+    /// Any place in the universe to update, used to look up its universe id
+    #[arg(long)]
+    pub place_id: Option<u64>,
+
+    /// Append every failure/warning to this file with a timestamp as it happens
+    #[arg(long)]
+    pub log_file: Option<String>,
+
+    /// After processing, write one CSV row per place and package it uses: how
+    /// often, whether it got replaced and why not
+    #[arg(long, value_name = "PATH")]
+    pub csv: Option<PathBuf>,
+
+    /// After processing, write a JSON list of every saved place file with its
+    /// path, size and SHA-256
+    #[arg(long, value_name = "PATH")]
+    pub output_manifest: Option<PathBuf>,
+
+    /// Only scan every --universe-id and report which packages are used in more
+    /// than one of them and which by a single place. Nothing is fetched,
+    /// replaced or published
+    #[arg(long, conflicts_with = "place_id")]
+    pub report_unused_packages: bool,
+
+    /// Print the size and download time of every package, largest first
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Stop all remaining work after this many seconds, still reporting failures
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Stop all remaining work at the first error instead of carrying on, for
+    /// debugging
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Download assets and places through Open Cloud with the API key instead
+    /// of the .ROBLOSECURITY cookie
+    #[arg(long)]
+    pub use_api_key_auth: bool,
+
+    /// Save the .ROBLOSECURITY to this file and reuse it on later runs while it
+    /// is still valid, instead of finding it again every time
+    #[arg(long, env = "RBXL_COOKIE_CACHE")]
+    pub cookie_cache: Option<String>,
+
+    /// Ignore the cached .ROBLOSECURITY and find it again
+    #[arg(long)]
+    pub refresh_cookie: bool,
+
+    /// Publish the saved places without asking for confirmation
+    #[arg(long, conflicts_with = "no_publish")]
+    pub publish: bool,
+
+    /// Never publish, only save the updated places locally
+    #[arg(long)]
+    pub no_publish: bool,
+
+    /// Ask before publishing each place instead of once for all of them, and
+    /// publish them one at a time
+    #[arg(long, conflicts_with_all = ["publish", "no_publish"])]
+    pub interactive_publish: bool,
+
+    /// When someone else published a place after it was downloaded, download it
+    /// again, redo its replacements and publish that instead, once
+    #[arg(long)]
+    pub retry_conflicts: bool,
+
+    /// After saving, delete the saved place files in the output directory whose
+    /// place is no longer in the universe. They're listed and confirmed first
+    #[arg(long)]
+    pub prune_output: bool,
+
+    /// Don't ask before --prune-output deletes anything
+    #[arg(long, requires = "prune_output")]
+    pub yes: bool,
+
+    /// Read the Open Cloud API key from this file when RBXL_API_KEY isn't set
+    #[arg(long)]
+    pub api_key_file: Option<PathBuf>,
+
+    /// Read the API key and .ROBLOSECURITY from the OS keyring when they aren't
+    /// set in the environment. Save them there with `store-credentials`
+    #[arg(long)]
+    pub use_keyring: bool,
+
+    /// Worker threads for the async runtime. Defaults to one per CPU. Can't be
+    /// set in the config file, since the runtime starts before it's read
+    #[arg(long, env = "RBXL_THREADS")]
+    pub threads: Option<usize>,
+
+    #[command(flatten)]
+    pub config: Config,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// List every PackageLink in the universe without fetching or replacing anything
+    Scan,
+    /// Compare the places saved in the output directory with their live versions
+    Diff {
+        /// List every added, removed and changed instance, not just the counts
+        #[arg(long)]
+        verbose: bool,
+    },
+    /// Save the API key and .ROBLOSECURITY in the OS keyring for --use-keyring
+    StoreCredentials,
+    /// Check the credentials, the output directory and the connection to each
+    /// Roblox endpoint without updating anything
+    Doctor,
+    /// Download a single package and print its instance tree
+    Inspect {
+        /// The package's asset id
+        package_id: String,
+        /// Also list the property names of the package's top-level instance(s)
+        #[arg(long)]
+        properties: bool,
+    },
+}
+
+// Finds the value of one option before clap gets to see the arguments
+fn early_arg(long: &str) -> Option<OsString> {
+    let prefix = format!("{}=", long);
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == long {
+            return args.next();
+        }
+        if let Some(value) = arg.to_str().and_then(|arg| arg.strip_prefix(&prefix)) {
+            return Some(value.into());
+        }
+    }
+    None
+}
+
+// The file --config names changes what clap's defaults are, so it's needed first
+fn config_file_arg() -> Option<PathBuf> {
+    early_arg("--config").map(PathBuf::from)
+}
+
+/// How many worker threads the runtime gets: `--threads`, else `RBXL_THREADS`,
+/// else one per CPU. Read before clap runs, since the runtime has to exist first
+pub fn worker_threads() -> Result<usize> {
+    let threads = early_arg("--threads")
+        .map(|threads| threads.to_string_lossy().into_owned())
+        .or_else(|| std::env::var("RBXL_THREADS").ok());
+    match threads {
+        Some(threads) => match threads.trim().parse::<usize>() {
+            Ok(threads) if threads > 0 => Ok(threads),
+            _ => bail!("--threads must be a positive number, got '{}'", threads),
+        },
+        None => Ok(std::thread::available_parallelism().map_or(1, |cpus| cpus.get())),
+    }
+}
+
+/// Parses the command line, using the `--config` file, if any, for defaults.
+/// That gives flags > environment variables > config file > built-in defaults.
+pub fn parse_args() -> Result<Args> {
+    let mut command = Args::command();
+
+    if let Some(path) = config_file_arg() {
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let table: toml::Table = contents
+            .parse()
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+
+        for (key, value) in table {
+            let id = key.replace('-', "_");
+            if id == "threads" {
+                bail!(
+                    "'threads' can't be set in config file {}, the runtime is started before it's read. Use --threads or RBXL_THREADS",
+                    path.display()
+                );
+            }
+            let known = id != "config_file"
+                && command
+                    .get_arguments()
+                    .any(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some());
+            if !known {
+                bail!("Unknown option '{}' in config file {}", key, path.display());
+            }
+
+            let values: Vec<OsString> = match value {
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| config_value(&key, item))
+                    .collect::<Result<_>>()?,
+                value => vec![config_value(&key, value)?],
+            };
+            command = command.mut_arg(id, |arg| arg.default_values(values));
+        }
+    }
+
+    Ok(Args::from_arg_matches(&command.get_matches())?)
+}
+
+fn config_value(key: &str, value: toml::Value) -> Result<OsString> {
+    Ok(match value {
+        toml::Value::String(value) => value.into(),
+        toml::Value::Integer(value) => value.to_string().into(),
+        toml::Value::Float(value) => value.to_string().into(),
+        toml::Value::Boolean(value) => value.to_string().into(),
+        _ => bail!(
+            "Option '{}' in the config file must be a string, number, boolean or list of them",
+            key
+        ),
+    })
+}
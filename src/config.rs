@@ -0,0 +1,311 @@
+use clap::{
+    Args, Parser, ValueEnum,
+    builder::{PossibleValuesParser, TypedValueParser},
+};
+use reqwest_retry::Jitter;
+use std::time::Duration;
+
+use crate::endpoints::RobloxEndpoints;
+
+/// Sent as the User-Agent unless --user-agent overrides it
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Everything that changes how the update pipeline behaves. The CLI flattens
+/// this into its own arguments; embedders can start from `Config::default()`.
+#[derive(Debug, Clone, Args)]
+pub struct Config {
+    /// Also replace PackageLinks that have AutoUpdate disabled
+    #[arg(long)]
+    pub force_all: bool,
+
+    /// Leave a package copy alone when its PackageLink is already at the
+    /// fetched version or later. Copies without a version are still replaced
+    #[arg(long)]
+    pub replace_only_if_newer: bool,
+
+    /// Restore locally edited properties on instances inside packages, not just
+    /// on the package root. Note this also reverts upstream changes to them
+    #[arg(long)]
+    pub keep_descendant_overrides: bool,
+
+    /// Names of package root children (comma separated) to keep from the old
+    /// copy of each package instead of taking them from the new version, like a
+    /// customized configuration Folder
+    #[arg(long, value_delimiter = ',')]
+    pub preserve_children: Vec<String>,
+
+    /// Remove the PackageLinks after updating, leaving the package contents as
+    /// plain instances that are no longer linked to the package
+    #[arg(long)]
+    pub strip_links: bool,
+
+    /// Replace packages even when their latest version has no content besides
+    /// the PackageLink, which empties the places using them
+    #[arg(long)]
+    pub allow_empty_packages: bool,
+
+    /// Destroy PackageLinks, along with their package, whose asset was deleted.
+    /// Private packages are only reported, since a bad cookie looks the same
+    #[arg(long)]
+    pub remove_broken: bool,
+
+    /// Lockfile mapping package asset ids to version numbers
+    #[arg(long, default_value = "packages.lock")]
+    pub lockfile: String,
+
+    /// Fetch the package versions pinned in the lockfile instead of the latest
+    #[arg(long)]
+    pub locked: bool,
+
+    /// Try the listed locations in order when an asset has no `source` format
+    #[arg(long)]
+    pub lenient_format: bool,
+
+    /// Download the currently live version of each place into ./backups before
+    /// publishing. Places that can't be backed up are not published
+    #[arg(long)]
+    pub backup: bool,
+
+    /// How many times to retry a transient HTTP failure. 0 disables retries
+    #[arg(long, default_value_t = 3)]
+    pub max_retries: u32,
+
+    /// How many times to download a package again when what came back isn't a
+    /// Roblox file or doesn't parse. Separate from the HTTP retries, which only
+    /// cover failed requests
+    #[arg(long, default_value_t = 0)]
+    pub max_package_retries: u32,
+
+    /// Random jitter applied to the exponential backoff between retries
+    #[arg(long, value_enum, default_value_t = RetryJitter::Full)]
+    pub retry_jitter: RetryJitter,
+
+    /// How many packages are downloaded at the same time
+    #[arg(long, default_value_t = 3)]
+    pub download_concurrency: usize,
+
+    /// Cap on how fast places and packages are downloaded from the CDN, in bytes
+    /// per second across all downloads together
+    #[arg(long)]
+    pub max_bytes_per_sec: Option<u64>,
+
+    /// Downloads that decompress to more than this many MB are staged in a temp
+    /// file instead of memory. 0 keeps them all in memory
+    #[arg(long, default_value_t = 64)]
+    pub spill_threshold_mb: u64,
+
+    /// Look up the total package download size first, and ask before continuing
+    /// when it's larger than this many megabytes
+    #[arg(long, value_name = "MB")]
+    pub max_download_mb: Option<u64>,
+
+    /// How many places are published at the same time. Publishing is more
+    /// sensitive to rate limits than downloading
+    #[arg(long, default_value_t = 1)]
+    pub publish_concurrency: usize,
+
+    /// Wait this many milliseconds between publishes to space them out under
+    /// the rate limits. Publishes go one at a time when this is set
+    #[arg(long)]
+    pub publish_delay_ms: Option<u64>,
+
+    /// Timeout in seconds for quick requests like asset metadata lookups
+    #[arg(long, default_value_t = 20)]
+    pub timeout_secs: u64,
+
+    /// Timeout in seconds for downloading place and package files from the CDN,
+    /// and for uploading places when publishing
+    #[arg(long, default_value_t = 120)]
+    pub download_timeout_secs: u64,
+
+    /// Proxy to send all requests through
+    #[arg(long, env = "HTTPS_PROXY")]
+    pub proxy: Option<String>,
+
+    /// Accept invalid TLS certificates, for proxies that intercept TLS
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// User-Agent sent with every request. Roblox tends to throttle generic
+    /// HTTP library user agents harder than ones that identify the tool
+    #[arg(long, env = "RBXL_USER_AGENT", default_value = DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+
+    /// Read places and packages from --places-dir and --packages-dir instead of
+    /// downloading them. Nothing is published
+    #[arg(long, requires_all = ["places_dir", "packages_dir"])]
+    pub offline: bool,
+
+    /// Directory of `<place id>.rbxl` files to update with --offline
+    #[arg(long)]
+    pub places_dir: Option<String>,
+
+    /// Directory of `<package id>.rbxm` files to update places with in --offline mode
+    #[arg(long)]
+    pub packages_dir: Option<String>,
+
+    /// Parse every updated place back after serializing it, and skip saving and
+    /// publishing it if that fails or instances went missing
+    #[arg(long)]
+    pub verify_output: bool,
+
+    /// Directory the updated places are saved to
+    #[arg(long, default_value = "rbxls")]
+    pub output_dir: String,
+
+    /// Reuse places already saved to the output directory by an earlier run
+    /// instead of processing them again, and packages it downloaded if they
+    /// still parse
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Save places gzipped as {id}.rbxl.gz. Studio can't open those directly,
+    /// but --resume and diff read them
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Also write each updated place's instance tree into {dir}/{place id} as
+    /// Rojo lays it out: directories, .lua scripts and .rbxmx models
+    #[arg(long, value_name = "DIR")]
+    pub export_rojo: Option<String>,
+
+    /// Save and publish places even when they have no PackageLinks
+    #[arg(long)]
+    pub force_rewrite: bool,
+
+    /// Leave the universe's root place alone, often the template new places
+    /// start from
+    #[arg(long)]
+    pub skip_root_place: bool,
+
+    /// Process and publish places even when the manifest in the output directory
+    /// says neither they nor their packages changed since they were last published
+    #[arg(long)]
+    pub force: bool,
+
+    /// Save and publish places even when none of their PackageLinks could be
+    /// replaced, e.g. because every package failed to download
+    #[arg(long)]
+    pub publish_unchanged: bool,
+
+    /// Only fetch and replace these package asset ids (comma separated). Every
+    /// other PackageLink is left untouched
+    #[arg(long, value_delimiter = ',')]
+    pub packages: Vec<String>,
+
+    /// Classes to look for while scanning. Only PackageLinks are replaced,
+    /// instances of any other class are just listed
+    #[arg(long, value_delimiter = ',', default_value = "PackageLink")]
+    pub only_scan_classes: Vec<String>,
+
+    /// Scan, update and save the places this many at a time instead of holding
+    /// every place in memory until all of them are processed
+    #[arg(long)]
+    pub max_places_in_memory: Option<usize>,
+
+    /// Only process the first N places of the universe, for quick test runs
+    #[arg(long, value_name = "N")]
+    pub limit_places: Option<usize>,
+
+    /// Only process places updated within this long, e.g. `7d` or `12h`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub since: Option<Duration>,
+
+    /// How many places have their packages replaced and are serialized at the
+    /// same time. Each one holds its whole DOM in memory
+    #[arg(long, default_value_t = 4)]
+    pub process_concurrency: usize,
+
+    /// How many pages of the places list may be fetched ahead of the place
+    /// downloads. Places are still downloaded in list order
+    #[arg(long, default_value_t = 2)]
+    pub pages_concurrency: usize,
+
+    /// Order the places list is fetched in. `desc` gets the most recently
+    /// created places first, handy with --limit-places
+    #[arg(long, value_enum, default_value_t = SortOrder::Asc)]
+    pub sort_order: SortOrder,
+
+    /// How many places are asked for per page of the places list. The API only
+    /// accepts these sizes
+    #[arg(
+        long,
+        default_value_t = 100,
+        value_parser = PossibleValuesParser::new(["10", "25", "50", "100"])
+            .map(|size| size.parse::<u32>().unwrap())
+    )]
+    pub page_size: u32,
+
+    /// Publish places as a Saved version for review instead of going live
+    #[arg(long, value_enum, default_value_t = VersionType::Published)]
+    pub version_type: VersionType,
+
+    #[command(flatten)]
+    pub endpoints: RobloxEndpoints,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            config: Config,
+        }
+
+        Defaults::parse_from(["roblox-packagelink-updater"]).config
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum RetryJitter {
+    None,
+    Full,
+    Bounded,
+}
+
+impl From<RetryJitter> for Jitter {
+    fn from(value: RetryJitter) -> Self {
+        match value {
+            RetryJitter::None => Jitter::None,
+            RetryJitter::Full => Jitter::Full,
+            RetryJitter::Bounded => Jitter::Bounded,
+        }
+    }
+}
+
+/// Order places are listed in, by when they were created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    /// The `sortOrder` query value the develop API expects
+    pub fn as_query(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "Asc",
+            SortOrder::Desc => "Desc",
+        }
+    }
+}
+
+/// Which kind of place version publishing creates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VersionType {
+    /// Saved to the place's version history without going live
+    Saved,
+    /// Goes live immediately
+    Published,
+}
+
+impl VersionType {
+    /// The `versionType` query value the Open Cloud place publishing API expects
+    pub fn as_query(&self) -> &'static str {
+        match self {
+            VersionType::Saved => "Saved",
+            VersionType::Published => "Published",
+        }
+    }
+}
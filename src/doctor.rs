@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow};
+use secrecy::SecretString;
+use serde_json::json;
+use std::path::Path;
+
+use crate::{Config, endpoints::RobloxEndpoints, roblox_cookie::validate_cookie};
+
+/// The outcome of one `doctor` check, with what was found either way
+pub struct Check {
+    pub name: String,
+    pub outcome: Result<String, String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, outcome: Result<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: outcome.map_err(|e| format!("{:#}", e)),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+// Open Cloud's introspection endpoint says whether a key exists and is enabled
+// without needing to know what it has access to
+async fn check_api_key(client: &reqwest::Client, apis: &str, api_key: &str) -> Result<String> {
+    if api_key.is_empty() {
+        return Err(anyhow!(
+            "not set, use RBXL_API_KEY, --api-key-file or --use-keyring"
+        ));
+    }
+
+    let response = client
+        .post(format!("{}/api-keys/v1/introspect", apis))
+        .json(&json!({ "apiKey": api_key }))
+        .send()
+        .await?;
+    match response.status() {
+        status if status.is_success() => Ok("valid".to_string()),
+        reqwest::StatusCode::BAD_REQUEST
+        | reqwest::StatusCode::UNAUTHORIZED
+        | reqwest::StatusCode::FORBIDDEN => Err(anyhow!("rejected, it's invalid or expired")),
+        status => Err(anyhow!("unexpected HTTP {} while checking it", status)),
+    }
+}
+
+async fn check_cookie(
+    client: &reqwest::Client,
+    endpoints: &RobloxEndpoints,
+    cookie: Option<&SecretString>,
+) -> Result<String> {
+    let Some(cookie) = cookie else {
+        return Err(anyhow!(
+            "not set, use RBXL_COOKIE, --cookie-cache or --use-keyring"
+        ));
+    };
+
+    if validate_cookie(client, endpoints, cookie).await? {
+        Ok("valid".to_string())
+    } else {
+        Err(anyhow!("rejected, it has expired or been logged out"))
+    }
+}
+
+// Writes and removes a scratch file, the same way saving a place would need to
+async fn check_output_dir(output_dir: &Path) -> Result<String> {
+    tokio::fs::create_dir_all(output_dir).await?;
+    let probe = output_dir.join(".doctor-probe");
+    tokio::fs::write(&probe, b"ok").await?;
+    tokio::fs::remove_file(&probe).await?;
+    Ok(format!("{} is writable", output_dir.display()))
+}
+
+// Any HTTP response at all means the endpoint can be reached
+async fn check_endpoint(client: &reqwest::Client, url: &str) -> Result<String> {
+    let response = client.get(url).send().await?;
+    Ok(format!("reachable (HTTP {})", response.status().as_u16()))
+}
+
+/// Checks the API key, the .ROBLOSECURITY, the output directory and each
+/// Roblox endpoint, in that order. Nothing is changed, and every check runs
+/// even when an earlier one fails
+pub async fn run_checks(
+    client: &reqwest::Client,
+    config: &Config,
+    api_key: &str,
+    cookie: Option<&SecretString>,
+) -> Vec<Check> {
+    let endpoints = config.endpoints.clone().trimmed();
+    let mut checks = vec![
+        Check::new(
+            "API key",
+            check_api_key(client, &endpoints.apis, api_key).await,
+        ),
+        Check::new(
+            ".ROBLOSECURITY",
+            check_cookie(client, &endpoints, cookie).await,
+        ),
+        Check::new(
+            "Output directory",
+            check_output_dir(Path::new(&config.output_dir)).await,
+        ),
+    ];
+
+    for (name, url) in [
+        ("develop", &endpoints.develop),
+        ("assetdelivery", &endpoints.assetdelivery),
+        ("apis", &endpoints.apis),
+        ("users", &endpoints.users),
+    ] {
+        checks.push(Check::new(
+            format!("Connectivity to {} ({})", name, url),
+            check_endpoint(client, url).await,
+        ));
+    }
+
+    checks
+}
@@ -0,0 +1,129 @@
+use rbx_dom_weak::{Instance, InstanceBuilder, WeakDom, ustr};
+use rbx_types::{Ref, Variant};
+
+/// Dotted path of an instance from the top of its DOM, like GetFullName
+pub fn full_name(dom: &WeakDom, referent: Ref) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    let mut current = dom.get_by_ref(referent);
+    while let Some(instance) = current {
+        if instance.parent().is_none() {
+            break;
+        }
+        names.push(&instance.name);
+        current = dom.get_by_ref(instance.parent());
+    }
+    names.reverse();
+    names.join(".")
+}
+
+/// WeakDom isn't Clone, so this copies every top-level instance into a fresh DOM
+pub fn clone_dom(dom: &WeakDom) -> WeakDom {
+    let mut copy = WeakDom::new(InstanceBuilder::new("DataModel"));
+    let copy_root = copy.root_ref();
+    for referent in dom.clone_multiple_into_external(dom.root().children(), &mut copy) {
+        copy.transfer_within(referent, copy_root);
+    }
+    copy
+}
+
+/// The package version a PackageLink says its copy is at. Which property holds
+/// it depends on how old the file is
+pub fn package_link_version(link: &Instance) -> Option<i64> {
+    ["VersionIdSerialize", "PackageIdVersion", "VersionId"]
+        .into_iter()
+        .find_map(|name| match link.properties.get(&ustr(name)) {
+            Some(Variant::Int64(version)) => Some(*version),
+            Some(Variant::Int32(version)) => Some(i64::from(*version)),
+            _ => None,
+        })
+}
+
+/// The version of a fetched package, from the PackageLink on its root. Nested
+/// packages are ignored
+pub fn fetched_package_version(package: &WeakDom) -> Option<i64> {
+    let root = package.get_by_ref(*package.root().children().first()?)?;
+    root.children()
+        .iter()
+        .filter_map(|child| package.get_by_ref(*child))
+        .find(|child| child.class == "PackageLink")
+        .and_then(package_link_version)
+}
+
+/// Pulls the numeric asset id out of a PackageId. Besides `rbxassetid://<id>`
+/// this accepts the legacy `http(s)://www.roblox.com/asset/?id=<id>` form and
+/// bare ids.
+pub fn parse_package_id(package_id: &str) -> Option<String> {
+    let package_id = package_id.trim();
+    let id = if let Some(id) = package_id.strip_prefix("rbxassetid://") {
+        id
+    } else if let Some(url) = package_id
+        .strip_prefix("http://")
+        .or_else(|| package_id.strip_prefix("https://"))
+    {
+        // Only Roblox's own asset URL, an id from anywhere else means nothing
+        let (location, query) = url.split_once('?')?;
+        let location = location.to_ascii_lowercase();
+        let host_and_path = location.strip_prefix("www.").unwrap_or(&location);
+        if host_and_path != "roblox.com/asset/" && host_and_path != "roblox.com/asset" {
+            return None;
+        }
+        query.split('&').find_map(|pair| {
+            pair.strip_prefix("id=")
+                .or_else(|| pair.strip_prefix("ID="))
+        })?
+    } else {
+        package_id
+    };
+
+    (!id.is_empty() && id.bytes().all(|b| b.is_ascii_digit())).then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_package_id;
+
+    #[test]
+    fn parses_rbxassetid() {
+        assert_eq!(
+            parse_package_id("rbxassetid://123"),
+            Some("123".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_legacy_asset_url() {
+        for package_id in [
+            "http://www.roblox.com/asset/?id=123",
+            "https://www.roblox.com/asset/?id=123",
+            "https://roblox.com/asset?ID=123",
+            "http://www.roblox.com/asset/?version=1&id=123",
+        ] {
+            assert_eq!(
+                parse_package_id(package_id),
+                Some("123".to_string()),
+                "{}",
+                package_id
+            );
+        }
+    }
+
+    #[test]
+    fn parses_bare_id() {
+        assert_eq!(parse_package_id(" 123 "), Some("123".to_string()));
+    }
+
+    #[test]
+    fn rejects_unparseable_ids() {
+        for package_id in [
+            "",
+            "rbxassetid://",
+            "rbxassetid://12a",
+            "https://www.roblox.com/asset/?id=",
+            "https://example.com/asset/?id=123",
+            "https://www.roblox.com/games/?id=123",
+            "Package",
+        ] {
+            assert_eq!(parse_package_id(package_id), None, "{}", package_id);
+        }
+    }
+}
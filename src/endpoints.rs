@@ -0,0 +1,69 @@
+use clap::Args;
+
+/// Base URLs of the Roblox web APIs the updater talks to, without a trailing
+/// slash. Defaults to production; point them at staging, a caching proxy or a
+/// mock server instead.
+#[derive(Debug, Clone, Args)]
+pub struct RobloxEndpoints {
+    /// Base URL of the develop API, for universe and places lists
+    #[arg(
+        long = "develop-url",
+        env = "RBXL_DEVELOP_URL",
+        default_value = "https://develop.roblox.com"
+    )]
+    pub develop: String,
+
+    /// Base URL of assetdelivery, for asset downloads with the .ROBLOSECURITY
+    #[arg(
+        long = "assetdelivery-url",
+        env = "RBXL_ASSETDELIVERY_URL",
+        default_value = "https://assetdelivery.roblox.com"
+    )]
+    pub assetdelivery: String,
+
+    /// Base URL of Open Cloud, for asset downloads with an API key, universes
+    /// and publishing
+    #[arg(
+        long = "apis-url",
+        env = "RBXL_APIS_URL",
+        default_value = "https://apis.roblox.com"
+    )]
+    pub apis: String,
+
+    /// Base URL of the users API, for checking a cached .ROBLOSECURITY
+    #[arg(
+        long = "users-url",
+        env = "RBXL_USERS_URL",
+        default_value = "https://users.roblox.com"
+    )]
+    pub users: String,
+}
+
+impl Default for RobloxEndpoints {
+    fn default() -> Self {
+        RobloxEndpoints {
+            develop: "https://develop.roblox.com".to_string(),
+            assetdelivery: "https://assetdelivery.roblox.com".to_string(),
+            apis: "https://apis.roblox.com".to_string(),
+            users: "https://users.roblox.com".to_string(),
+        }
+    }
+}
+
+impl RobloxEndpoints {
+    /// Every base URL sent in with a trailing slash has it removed, so paths
+    /// can be appended as-is
+    pub fn trimmed(mut self) -> Self {
+        for url in [
+            &mut self.develop,
+            &mut self.assetdelivery,
+            &mut self.apis,
+            &mut self.users,
+        ] {
+            while url.ends_with('/') {
+                url.pop();
+            }
+        }
+        self
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use std::{fmt, path::Path, time::SystemTime};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+        watch,
+    },
+};
+
+/// How bad a reported [`Failure`] is, worst first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Something went wrong and a place or package was left behind
+    Error,
+    /// Something looks off, but the run carried on with it
+    Warning,
+    /// Left alone on purpose, by a flag or because nothing needed doing
+    Skipped,
+    /// Worth knowing, nothing went wrong
+    Note,
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Skipped => "skipped",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// A failure, skip or note collected during a run, along with what it's about
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub level: Severity,
+    /// What the message is about, like `place Lobby (123)` or `package 456`
+    pub context: String,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(level: Severity, context: impl Into<String>, message: impl Into<String>) -> Self {
+        Failure {
+            level,
+            context: context.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn error(context: impl Into<String>, message: impl Into<String>) -> Self {
+        Failure::new(Severity::Error, context, message)
+    }
+
+    pub fn warning(context: impl Into<String>, message: impl Into<String>) -> Self {
+        Failure::new(Severity::Warning, context, message)
+    }
+
+    pub fn skipped(context: impl Into<String>, message: impl Into<String>) -> Self {
+        Failure::new(Severity::Skipped, context, message)
+    }
+
+    pub fn note(context: impl Into<String>, message: impl Into<String>) -> Self {
+        Failure::new(Severity::Note, context, message)
+    }
+
+    /// The place or package id the failure is about, taken from its context:
+    /// the `123` of `place Lobby (123)` or `package 123`
+    pub fn subject_id(&self) -> Option<u64> {
+        let context = self.context.strip_suffix(')').unwrap_or(&self.context);
+        context
+            .rsplit(['(', ' '])
+            .next()
+            .and_then(|id| id.parse().ok())
+    }
+
+    /// Orders failures by the id they're about, then by what they say, so a
+    /// summary reads the same however the concurrent work finished. Failures
+    /// about the run as a whole come first
+    pub fn sort_key(&self) -> (Option<u64>, &str, &str) {
+        (self.subject_id(), &self.context, &self.message)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.message)
+    }
+}
+
+/// Reports a failure: logs it right away at a matching level and sends it on to
+/// be listed again in the final summary.
+pub fn report(failed_tx: &UnboundedSender<Failure>, failure: Failure) {
+    match failure.level {
+        Severity::Error => tracing::error!("{}", failure),
+        Severity::Warning => tracing::warn!("{}", failure),
+        Severity::Skipped | Severity::Note => tracing::info!("{}", failure),
+    }
+    let _ = failed_tx.send(failure);
+}
+
+/// Passes every failure on through the returned receiver unchanged, and sends
+/// the first error on the returned watch, for --fail-fast to stop at.
+pub fn watch_first_error(
+    mut failed_rx: UnboundedReceiver<Failure>,
+) -> (UnboundedReceiver<Failure>, watch::Receiver<Option<String>>) {
+    let (forward_tx, forward_rx) = unbounded_channel();
+    let (error_tx, error_rx) = watch::channel(None);
+
+    tokio::spawn(async move {
+        while let Some(failure) = failed_rx.recv().await {
+            if failure.level == Severity::Error && error_tx.borrow().is_none() {
+                let _ = error_tx.send(Some(failure.to_string()));
+            }
+            let _ = forward_tx.send(failure);
+        }
+    });
+
+    (forward_rx, error_rx)
+}
+
+/// Appends every failure message to `path` with a timestamp as soon as it's
+/// sent, then passes it on through the returned receiver unchanged.
+pub async fn tee_to_log_file(
+    mut failed_rx: UnboundedReceiver<Failure>,
+    path: &Path,
+) -> Result<UnboundedReceiver<Failure>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let (forward_tx, forward_rx) = unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut write_failed = false;
+        while let Some(msg) = failed_rx.recv().await {
+            if !write_failed {
+                let line = format!(
+                    "[{}] {} {}\n",
+                    humantime::format_rfc3339_seconds(SystemTime::now()),
+                    msg.level.label(),
+                    msg
+                );
+                if let Err(e) = file.write_all(line.as_bytes()).await {
+                    tracing::error!("Failed to write to log file, no longer logging: {}", e);
+                    write_failed = true;
+                }
+            }
+            let _ = forward_tx.send(msg);
+        }
+        let _ = file.flush().await;
+    });
+
+    Ok(forward_rx)
+}
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use secrecy::{ExposeSecret, SecretString};
+
+/// Every credential is stored under this service name in the OS keyring
+const SERVICE: &str = "roblox-packagelink-updater";
+const API_KEY_ENTRY: &str = "api-key";
+const COOKIE_ENTRY: &str = "roblosecurity";
+
+fn entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, name)
+        .with_context(|| format!("Failed to open keyring entry {}", name))
+}
+
+// A missing entry is the same as nothing stored
+fn load(name: &str) -> Result<Option<SecretString>> {
+    match entry(name)?.get_password() {
+        Ok(secret) if secret.trim().is_empty() => Ok(None),
+        Ok(secret) => Ok(Some(secret.trim().to_string().into())),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read keyring entry {}", name)),
+    }
+}
+
+fn store(name: &str, secret: &SecretString) -> Result<()> {
+    entry(name)?
+        .set_password(secret.expose_secret())
+        .with_context(|| format!("Failed to write keyring entry {}", name))
+}
+
+/// The Open Cloud API key saved by [`store_api_key`], if any
+pub fn load_api_key() -> Result<Option<SecretString>> {
+    load(API_KEY_ENTRY)
+}
+
+/// The .ROBLOSECURITY saved by [`store_cookie`], if any
+pub fn load_cookie() -> Result<Option<SecretString>> {
+    load(COOKIE_ENTRY)
+}
+
+/// Saves the Open Cloud API key in the OS keyring for `--use-keyring`
+pub fn store_api_key(api_key: &SecretString) -> Result<()> {
+    store(API_KEY_ENTRY, api_key)
+}
+
+/// Saves the .ROBLOSECURITY in the OS keyring for `--use-keyring`
+pub fn store_cookie(cookie: &SecretString) -> Result<()> {
+    store(COOKIE_ENTRY, cookie)
+}
@@ -0,0 +1,37 @@
+//! Finds every PackageLink in a Roblox universe, swaps in the latest version of
+//! each package and republishes the places. [`Updater`] runs the whole pipeline
+//! or any of its phases on their own.
+
+pub mod asset;
+pub mod asset_response;
+pub mod cancel;
+pub mod config;
+pub mod doctor;
+pub mod dom_util;
+pub mod endpoints;
+pub mod failure_log;
+pub mod keyring_store;
+pub mod lockfile;
+pub mod manifest;
+pub mod open_cloud_asset_response;
+pub mod output_manifest;
+pub mod package_overrides;
+pub mod place_diff;
+pub mod place_universe_response;
+pub mod progress;
+pub mod publish_response;
+pub mod roblox_cookie;
+pub mod rojo_export;
+pub mod saved_file;
+pub mod throttle;
+pub mod universe_places_response;
+pub mod universe_response;
+mod updater;
+pub mod usage_report;
+
+pub use config::{Config, RetryJitter, SortOrder, VersionType};
+pub use updater::{
+    BatchedUpdate, BrokenPackage, BrokenReference, FetchedPackages, PackageStats, PlaceData,
+    PublishDecision, PublishResult, SavedPlace, ScannedInstance, ToWork, Updater,
+    broken_references, missing_packages, unique_package_ids,
+};
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+    path::Path,
+};
+
+use crate::{cancel::write_atomically, dom_util::fetched_package_version};
+
+/// Maps package asset ids to the version that should be fetched for them.
+#[derive(Debug, Default)]
+pub struct Lockfile {
+    pub packages: BTreeMap<String, u64>,
+}
+
+impl Lockfile {
+    // A missing lockfile is the same as an empty one
+    pub async fn load(path: &Path) -> Result<Lockfile> {
+        if !tokio::fs::try_exists(path).await? {
+            return Ok(Lockfile::default());
+        }
+
+        let contents = tokio::fs::read_to_string(path).await?;
+        let packages = serde_json::from_str(&contents)?;
+        Ok(Lockfile { packages })
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(&self.packages)? + "\n";
+        write_atomically(path, contents.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Records the version each fetched package resolved to. Packages whose
+    /// version can't be read keep their previously pinned version, if any.
+    /// Returns whether anything changed.
+    pub fn record(&mut self, versions: BTreeMap<String, u64>) -> bool {
+        let mut changed = false;
+        for (package_id, version) in versions {
+            changed |= self.packages.insert(package_id, version) != Some(version);
+        }
+        changed
+    }
+}
+
+/// Reads the version of each package from its own PackageLink. Parses every
+/// package, so run it on the blocking pool.
+pub fn package_versions(package_bytes_map: &HashMap<String, Vec<u8>>) -> BTreeMap<String, u64> {
+    package_bytes_map
+        .iter()
+        .filter_map(|(package_id, bytes)| {
+            let dom = rbx_binary::from_reader(Cursor::new(bytes)).ok()?;
+            let version = u64::try_from(fetched_package_version(&dom)?).ok()?;
+            Some((package_id.clone(), version))
+        })
+        .collect()
+}
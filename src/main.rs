@@ -1,573 +1,710 @@
-use anyhow::Result;
-use flate2::read::GzDecoder;
-use futures::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
-use rbx_dom_weak::{WeakDom, ustr};
-use rbx_types::{Ref, Variant};
-use reqwest::{Response, Url, cookie::Jar};
-use reqwest_middleware::ClientBuilder;
-use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
+use anyhow::{Context, Result, bail};
+use console::style;
+use rbx_dom_weak::WeakDom;
+use rbx_types::{Ref, VariantType};
+use roblox_packagelink_updater::{
+    BrokenReference, PackageStats, PlaceData, PublishDecision, PublishResult, SavedPlace, Updater,
+    VersionType,
+    asset::AssetAuth,
+    broken_references,
+    cancel::{Interruption, until_interrupted},
+    doctor::{self, Check},
+    dom_util::full_name,
+    failure_log::{Failure, Severity, tee_to_log_file, watch_first_error},
+    keyring_store, missing_packages,
+    output_manifest::write_output_manifest,
+    place_diff::PlaceDiff,
+    progress::{self, ProgressWriter},
+    roblox_cookie::{get_roblosecurity, load_cached_cookie, save_cached_cookie, validate_cookie},
+    unique_package_ids,
+    usage_report::{CrossUniverseUsage, package_usage, write_usage_csv},
+};
 use rustyline::DefaultEditor;
+use secrecy::{ExposeSecret, SecretString};
 use std::{
-    collections::{HashMap, HashSet},
-    io::{Cursor, Read},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::IsTerminal,
     path::Path,
-    sync::Arc,
+    process::ExitCode,
+    time::Duration,
 };
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
-mod asset_response;
-use asset_response::AssetResponse;
-
-mod universe_places_response;
-use universe_places_response::UniversePlacesResponse;
-
-mod roblox_cookie;
-use roblox_cookie::get_roblosecurity;
+mod cli;
+use cli::Command;
 
-async fn decompress_if_needed(binary_response: Response) -> Result<Vec<u8>> {
-    // weird bug reqwest wouldn't decompress it so i had to add this
-    let is_gzipped = binary_response
-        .headers()
-        .get(reqwest::header::CONTENT_ENCODING)
-        .map_or(false, |val| val == "gzip");
-
-    let body_bytes = binary_response.bytes().await?;
-    let mut decompressed_bytes = Vec::new();
-
-    if is_gzipped {
-        let mut decoder = GzDecoder::new(&body_bytes[..]);
-        decoder.read_to_end(&mut decompressed_bytes)?;
-    } else {
-        decompressed_bytes = body_bytes.to_vec();
+fn print_package_links(places_data: &[PlaceData]) {
+    for place in places_data {
+        println!(
+            "
+{} (id: {}): {} PackageLink(s)",
+            place.name,
+            place.id,
+            place.to_work.len()
+        );
+        for work in place.to_work.iter() {
+            println!(
+                "> {} in {} ({})",
+                work.package_id_numbers,
+                work.package_name,
+                full_name(&place.dom, work.package_link_group)
+            );
+        }
+        for found in place.other_matches.iter() {
+            match &found.package_id {
+                Some(package_id) => println!(
+                    "> {} {} (PackageId {}, not replaced)",
+                    found.class, found.path, package_id
+                ),
+                None => println!("> {} {} (not replaced)", found.class, found.path),
+            }
+        }
     }
-
-    return Ok(decompressed_bytes);
 }
 
-struct ToWork {
-    package_id_numbers: String,
-    package_link: Ref,
-    package_link_group: Ref,
-    package_link_parent: Ref,
+fn print_instance_tree(dom: &WeakDom, referent: Ref, depth: usize) {
+    let Some(instance) = dom.get_by_ref(referent) else {
+        return;
+    };
+    println!("{}{} {}", "  ".repeat(depth), instance.class, instance.name);
+    for child in instance.children() {
+        print_instance_tree(dom, *child, depth + 1);
+    }
 }
 
-struct PlaceData {
-    id: u64,
-    name: String,
-    dom: WeakDom,
-    to_work: Vec<ToWork>,
-}
+fn print_properties(dom: &WeakDom) {
+    for referent in dom.root().children() {
+        let Some(instance) = dom.get_by_ref(*referent) else {
+            continue;
+        };
+        let mut properties: Vec<(&str, VariantType)> = instance
+            .properties
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.ty()))
+            .collect();
+        properties.sort_by_key(|(name, _)| *name);
 
-struct SavedPlace {
-    id: u64,
-    name: String,
-    buffer: Vec<u8>,
+        println!(
+            "\n{} {}: {} properties",
+            instance.class,
+            instance.name,
+            properties.len()
+        );
+        for (name, ty) in properties {
+            println!("> {}: {:?}", name, ty);
+        }
+    }
 }
 
-async fn collect_places_and_package_ids(
-    client: Arc<reqwest_middleware::ClientWithMiddleware>,
-    universe_id: u64,
-    spinner_style: ProgressStyle,
-    failed_tx: UnboundedSender<String>,
-) -> Result<Vec<PlaceData>> {
-    let universe_fetch_pb = ProgressBar::new(1);
-    universe_fetch_pb.set_style(spinner_style.clone());
-    universe_fetch_pb.set_prefix("[universe]");
-    universe_fetch_pb.set_message("Fetching places list");
-
-    let response = client
-        .get(format!(
-            "https://develop.roblox.com/v1/universes/{universe_id}/places?sortOrder=Asc&limit=100"
-        ))
-        .send()
-        .await?
-        .json::<UniversePlacesResponse>()
-        .await?;
-
-    universe_fetch_pb.finish_and_clear();
+fn print_package_stats(package_stats: &[PackageStats]) {
+    let mut sorted: Vec<&PackageStats> = package_stats.iter().collect();
+    sorted.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.id.cmp(&b.id)));
 
     println!(
         "
-Found places:"
+Package download stats (largest first):"
     );
-    for place in response.data() {
-        println!("> {} (id: {})", place.name(), place.id());
-    }
-
-    // Download each place once, parse and record PackageLink occurrences
-    let places_pb = ProgressBar::new(response.data().len() as u64);
-    places_pb.set_style(spinner_style.clone());
-    places_pb.set_prefix("[places]");
-
-    let mut places_data: Vec<PlaceData> = Vec::new();
-
-    for place in response.data().iter().cloned() {
-        places_pb.set_message(format!(
-            "Downloading place {} ({})",
-            place.name(),
-            place.id()
-        ));
-        // Fetch place asset metadata
-        let place_asset_resp = client
-            .get(format!(
-                "https://assetdelivery.roblox.com/v2/asset/?id={}",
-                place.id()
-            ))
-            .send()
-            .await;
-
-        let place_asset_resp = match place_asset_resp {
-            Ok(r) => r,
-            Err(e) => {
-                let msg = format!(
-                    "Failed to fetch asset metadata for place {} {}: {}",
-                    place.name(),
-                    place.id(),
-                    e
-                );
-                let _ = failed_tx.send(msg);
-                places_pb.inc(1);
-                continue;
+    for stats in sorted.iter() {
+        println!(
+            "> {}: {:.1} KiB in {:.2}s, {:.1} KiB/s (sha256 {}){}{}{}",
+            stats.id,
+            stats.bytes as f64 / 1024.0,
+            stats.elapsed.as_secs_f64(),
+            stats.bytes as f64 / 1024.0 / stats.elapsed.as_secs_f64().max(0.001),
+            stats.sha256,
+            if stats.cdn_refreshed {
+                ", CDN URL expired and was refreshed"
+            } else {
+                ""
+            },
+            if stats.location_index > 0 {
+                format!(", from source location {}", stats.location_index)
+            } else {
+                String::new()
+            },
+            if stats.attempts > 1 {
+                format!(", took {} downloads", stats.attempts)
+            } else {
+                String::new()
             }
-        };
+        );
+    }
 
-        let place_asset_json = match place_asset_resp.json::<AssetResponse>().await {
-            Ok(j) => j,
-            Err(e) => {
-                let msg = format!(
-                    "Failed to parse asset metadata for place {} {}: {}",
-                    place.name(),
-                    place.id(),
-                    e
-                );
-                let _ = failed_tx.send(msg);
-                places_pb.inc(1);
-                continue;
-            }
-        };
+    let refreshed = package_stats
+        .iter()
+        .filter(|stats| stats.cdn_refreshed)
+        .count();
+    if refreshed > 0 {
+        println!(
+            "{} of {} package download(s) needed a fresh CDN URL",
+            refreshed,
+            package_stats.len()
+        );
+    }
 
-        // Find CDN source
-        let mut cdn = None;
-        for location in place_asset_json.locations() {
-            if location.asset_format() == "source" {
-                cdn = Some(location.location());
-                break;
-            }
+    let fell_back = package_stats
+        .iter()
+        .filter(|stats| stats.location_index > 0)
+        .count();
+    if fell_back > 0 {
+        println!(
+            "{} of {} package download(s) only worked from a fallback source location",
+            fell_back,
+            package_stats.len()
+        );
+    }
+
+    let mut ids_by_hash: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for stats in package_stats {
+        ids_by_hash
+            .entry(&stats.sha256)
+            .or_default()
+            .push(&stats.id);
+    }
+    let duplicates: Vec<Vec<&str>> = ids_by_hash
+        .into_values()
+        .filter(|ids| ids.len() > 1)
+        .collect();
+    if !duplicates.is_empty() {
+        println!(
+            "
+Packages with identical contents:"
+        );
+        for ids in duplicates {
+            println!("> {}", ids.join(", "));
         }
+    }
+}
 
-        if cdn.is_none() {
-            let msg = format!(
-                "CDN source not found for place {} {}",
-                place.name(),
-                place.id()
-            );
-            let _ = failed_tx.send(msg);
-            places_pb.inc(1);
+fn print_place_diffs(diffs: &[(u64, PlaceDiff)], verbose: bool) {
+    if diffs.is_empty() {
+        println!(
+            "
+No saved places to compare."
+        );
+        return;
+    }
+
+    println!(
+        "
+Changes compared to the live places:"
+    );
+    for (place_id, diff) in diffs {
+        println!(
+            "> {}: {} added, {} removed, {} changed",
+            place_id,
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+        if !verbose {
             continue;
         }
+        for path in diff.added.iter() {
+            println!("  + {}", path);
+        }
+        for path in diff.removed.iter() {
+            println!("  - {}", path);
+        }
+        for path in diff.changed.iter() {
+            println!("  ~ {}", path);
+        }
+    }
+}
 
-        let cdn = cdn.unwrap();
-        places_pb.set_message(format!("Fetching CDN for place {}: {}", place.id(), cdn));
-        let place_binary_response = match client.get(cdn).send().await {
-            Ok(r) => r,
-            Err(e) => {
-                let msg = format!(
-                    "Failed to GET place CDN {} for {} {}: {}",
-                    cdn,
-                    place.name(),
-                    place.id(),
-                    e
-                );
-                let _ = failed_tx.send(msg);
-                places_pb.inc(1);
-                continue;
-            }
-        };
+fn print_publish_results(publish_results: &[PublishResult], version_type: VersionType) {
+    let mut sorted: Vec<&PublishResult> = publish_results.iter().collect();
+    sorted.sort_by_key(|result| (result.outcome.is_ok(), result.id));
 
-        let place_bytes = match decompress_if_needed(place_binary_response).await {
-            Ok(b) => b,
-            Err(e) => {
-                let msg = format!(
-                    "Failed to decompress place {} {}: {}",
-                    place.name(),
-                    place.id(),
-                    e
-                );
-                let _ = failed_tx.send(msg);
-                places_pb.inc(1);
-                continue;
-            }
-        };
+    println!(
+        "
+Publish results:"
+    );
+    for result in sorted.iter() {
+        match &result.outcome {
+            Ok(version) => println!(
+                "> {} (id: {}): published as {} version {}",
+                result.name,
+                result.id,
+                version_type.as_query(),
+                version
+            ),
+            Err(reason) => println!("> {} (id: {}): FAILED, {}", result.name, result.id, reason),
+        }
+    }
 
-        places_pb.set_message(format!("Parsing place DOM {}", place.id()));
-        let reader = Cursor::new(place_bytes);
-        let dom = match rbx_binary::from_reader(reader) {
-            Ok(d) => d,
-            Err(e) => {
-                let msg = format!(
-                    "Failed to parse RBX binary for place {} {}: {}",
-                    place.name(),
-                    place.id(),
-                    e
-                );
-                let _ = failed_tx.send(msg);
-                places_pb.inc(1);
-                continue;
-            }
-        };
+    let total = publish_results.len();
+    let succeeded = publish_results
+        .iter()
+        .filter(|result| result.outcome.is_ok())
+        .count();
+    println!(
+        "Publishing complete as {} versions: {} succeeded, {} failed (out of {})",
+        version_type.as_query(),
+        succeeded,
+        total - succeeded,
+        total
+    );
+}
 
-        // Scan for PackageLink instances
-        let mut to_work: Vec<ToWork> = Vec::new();
-        for instance in dom.descendants() {
-            if instance.class == "PackageLink" {
-                // Get PackageId
-                let package_id = match instance.properties.get(&ustr("PackageId")) {
-                    Some(Variant::ContentId(id)) => id.clone(),
-                    _ => {
-                        let msg = format!(
-                            "PackageLink without valid PackageId in place {} {}",
-                            place.name(),
-                            place.id()
-                        );
-                        let _ = failed_tx.send(msg);
-                        continue;
-                    }
-                };
+// The packages whose failed downloads left the most places untouched come first
+fn print_missing_packages(missing: &BTreeMap<String, usize>) {
+    if missing.is_empty() {
+        return;
+    }
 
-                let package_id_numbers = match package_id.as_str().strip_prefix("rbxassetid://") {
-                    Some(s) => s.to_string(),
-                    None => {
-                        let msg = format!(
-                            "PackageId had unexpected format '{}' in place {} {}",
-                            package_id.as_str(),
-                            place.name(),
-                            place.id()
-                        );
-                        let _ = failed_tx.send(msg);
-                        continue;
-                    }
-                };
+    let mut sorted: Vec<(&String, &usize)> = missing.iter().collect();
+    sorted.sort_by_key(|(_, places)| std::cmp::Reverse(**places));
 
-                let package_link_group = instance.parent();
-                let package_link = instance.referent();
-                let package_link_parent = dom.get_by_ref(package_link_group).unwrap().parent();
+    println!(
+        "
+Packages referenced but never fetched:"
+    );
+    for (package_id, places) in sorted {
+        println!("> {}: referenced by {} place(s)", package_id, places);
+    }
+}
 
-                to_work.push(ToWork {
-                    package_id_numbers,
-                    package_link,
-                    package_link_group,
-                    package_link_parent,
-                });
-            }
+fn print_cross_universe_usage(usage: &CrossUniverseUsage) {
+    println!(
+        "
+Packages and the places using them:"
+    );
+    for (package_id, users) in &usage.packages {
+        println!("> {}: used by {} place(s)", package_id, users.len());
+        for user in users {
+            println!(
+                "    universe {}: {} (id: {})",
+                user.universe_id, user.place_name, user.place_id
+            );
         }
-
-        places_data.push(PlaceData {
-            id: *place.id(),
-            name: place.name().to_string(),
-            dom,
-            to_work,
-        });
-
-        places_pb.inc(1);
     }
 
-    places_pb.finish_with_message("Finished scanning places");
+    let shared: Vec<_> = usage.shared().collect();
+    println!(
+        "
+{} package(s) used in more than one universe:",
+        shared.len()
+    );
+    for (package_id, users) in shared {
+        let universes: BTreeSet<u64> = users.iter().map(|user| user.universe_id).collect();
+        println!(
+            "> {}: universes {}",
+            package_id,
+            universes
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
 
-    Ok(places_data)
+    let single_place: Vec<_> = usage.single_place().collect();
+    println!(
+        "
+{} package(s) used by a single place:",
+        single_place.len()
+    );
+    for (package_id, user) in single_place {
+        println!(
+            "> {}: {} (id: {}) in universe {}",
+            package_id, user.place_name, user.place_id, user.universe_id
+        );
+    }
 }
 
-async fn fetch_package_assets(
-    client: Arc<reqwest_middleware::ClientWithMiddleware>,
-    package_ids: Vec<String>,
-    spinner_style: ProgressStyle,
-    failed_tx: UnboundedSender<String>,
-) -> HashMap<String, Vec<u8>> {
-    let packages_pb = ProgressBar::new(package_ids.len() as u64);
-    packages_pb.set_style(spinner_style.clone());
-    packages_pb.set_prefix("[packages]");
-
-    let package_results =
-        futures::stream::iter(package_ids.into_iter().map(|package_id_numbers| {
-            let client = Arc::clone(&client);
-            let packages_pb = packages_pb.clone();
-            let failed_tx = failed_tx.clone();
-            async move {
-                packages_pb.set_message(format!("Finding CDN for package {}", package_id_numbers));
-
-                // Get asset metadata
-                let asset_meta = match client
-                    .get(format!(
-                        "https://assetdelivery.roblox.com/v2/asset/?id={}",
-                        package_id_numbers
-                    ))
-                    .send()
-                    .await
-                {
-                    Ok(r) => match r.json::<AssetResponse>().await {
-                        Ok(j) => j,
-                        Err(e) => {
-                            let msg = format!(
-                                "Failed parse package asset metadata {}: {}",
-                                package_id_numbers, e
-                            );
-                            let _ = failed_tx.send(msg);
-                            packages_pb.inc(1);
-                            return Err((package_id_numbers, "parse_meta_failed".to_string()));
-                        }
-                    },
-                    Err(e) => {
-                        let msg = format!(
-                            "Failed GET package asset metadata {}: {}",
-                            package_id_numbers, e
-                        );
-                        let _ = failed_tx.send(msg);
-                        packages_pb.inc(1);
-                        return Err((package_id_numbers, "meta_failed".to_string()));
-                    }
-                };
-
-                let mut cdn = None;
-                for location in asset_meta.locations() {
-                    if location.asset_format() == "source" {
-                        cdn = Some(location.location());
-                        break;
-                    }
-                }
+// A place that shrank a lot may have had a package replaced by an empty one, so
+// drops stand out
+fn instance_delta(saved: &SavedPlace) -> String {
+    let delta = saved.instances_after as i64 - saved.instances_before as i64;
+    let summary = format!(
+        "{} -> {} ({:+})",
+        saved.instances_before, saved.instances_after, delta
+    );
+    if delta < 0 {
+        style(summary).yellow().to_string()
+    } else {
+        summary
+    }
+}
 
-                if cdn.is_none() {
-                    let msg = format!("Failed to find CDN for package {}", package_id_numbers);
-                    let _ = failed_tx.send(msg);
-                    packages_pb.inc(1);
-                    return Err((package_id_numbers, "cdn_not_found".to_string()));
-                }
+fn print_broken_references(references: &[BrokenReference]) {
+    if references.is_empty() {
+        return;
+    }
 
-                let cdn = cdn.unwrap();
-                packages_pb.set_message(format!(
-                    "Downloading package {} from CDN",
-                    package_id_numbers
-                ));
-                let package_binary_response = match client.get(cdn).send().await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        let msg = format!(
-                            "Failed GET package CDN {} for {}: {}",
-                            cdn, package_id_numbers, e
-                        );
-                        let _ = failed_tx.send(msg);
-                        packages_pb.inc(1);
-                        return Err((package_id_numbers, "cdn_get_failed".to_string()));
-                    }
-                };
+    let mut sorted: Vec<&BrokenReference> = references.iter().collect();
+    sorted.sort_by(|a, b| {
+        (a.place_id, &a.package_id, &a.path).cmp(&(b.place_id, &b.package_id, &b.path))
+    });
 
-                let package_bytes = match decompress_if_needed(package_binary_response).await {
-                    Ok(b) => b,
-                    Err(e) => {
-                        let msg =
-                            format!("Failed decompress package {}: {}", package_id_numbers, e);
-                        let _ = failed_tx.send(msg);
-                        packages_pb.inc(1);
-                        return Err((package_id_numbers, "decompress_failed".to_string()));
-                    }
-                };
+    println!(
+        "
+PackageLinks pointing at broken packages:"
+    );
+    for reference in sorted {
+        println!(
+            "> {} (id: {}): {} uses package {}, which is {}",
+            reference.place_name,
+            reference.place_id,
+            reference.path,
+            reference.package_id,
+            reference.reason.describe()
+        );
+    }
+}
 
-                packages_pb.inc(1);
-                Ok((package_id_numbers, package_bytes))
-            }
-        }))
-        .buffer_unordered(3)
-        .collect::<Vec<Result<(String, Vec<u8>), (String, String)>>>()
-        .await;
-
-    packages_pb.finish_with_message("Finished fetching packages");
-
-    // Collect successful package bytes
-    let mut package_bytes_map: HashMap<String, Vec<u8>> = HashMap::new();
-    for res in package_results.into_iter() {
-        match res {
-            Ok((id, bytes)) => {
-                package_bytes_map.insert(id, bytes);
-            }
-            Err((id, _)) => {
-                let msg = format!(
-                    "Package {} failed to fetch (see earlier messages). Leaving PackageLink(s) untouched.",
-                    id
-                );
-                let _ = failed_tx.send(msg);
-            }
+fn print_checks(checks: &[Check]) {
+    for check in checks {
+        match &check.outcome {
+            Ok(detail) => println!("{} {}: {}", style("PASS").green(), check.name, detail),
+            Err(reason) => println!("{} {}: {}", style("FAIL").red(), check.name, reason),
         }
     }
-
-    package_bytes_map
 }
 
-async fn process_places_and_save(
-    places_data: Vec<PlaceData>,
-    package_bytes_map: HashMap<String, Vec<u8>>,
-    spinner_style: ProgressStyle,
-    failed_tx: UnboundedSender<String>,
-) -> Result<Vec<SavedPlace>> {
-    let save_pb = ProgressBar::new(places_data.len() as u64);
-    save_pb.set_style(spinner_style.clone());
-    save_pb.set_prefix("[save]");
-
-    let mut saved_places: Vec<SavedPlace> = Vec::new();
-
-    for mut place in places_data.into_iter() {
-        save_pb.set_message(format!(
-            "Processing replacements for place {} ({})",
-            place.name, place.id
-        ));
-        let mut replacements = 0u32;
-        for work in place.to_work.iter() {
-            if let Some(bytes) = package_bytes_map.get(&work.package_id_numbers) {
-                let package_reader = Cursor::new(bytes.clone());
-                let mut package_dom = match rbx_binary::from_reader(package_reader) {
-                    Ok(d) => d,
-                    Err(e) => {
-                        let msg = format!(
-                            "Failed to parse package DOM for package {}: {}",
-                            work.package_id_numbers, e
-                        );
-                        let _ = failed_tx.send(msg);
-                        continue;
-                    }
-                };
-
-                let package_root = package_dom.root().children()[0];
+// Asks a yes/no question. Without a terminal to ask on, the answer is always no
+fn confirm(rl: &mut DefaultEditor, interactive: bool, prompt: &str) -> Result<bool> {
+    if !interactive {
+        return Ok(false);
+    }
+    Ok(rl.readline(prompt)?.to_lowercase() == "yes")
+}
 
-                // Transfer the old PackageLink into package_dom
-                place
-                    .dom
-                    .transfer(work.package_link, &mut package_dom, package_root);
+// Lists the saved places whose place is no longer in the universe and deletes
+// them, asking first unless --yes
+async fn prune_output(
+    updater: &Updater,
+    universe_id: u64,
+    rl: &mut DefaultEditor,
+    interactive: bool,
+    yes: bool,
+) -> Result<()> {
+    let stale = updater.stale_saved_places(universe_id).await?;
+    if stale.is_empty() {
+        println!(":: Nothing to prune, every saved place is still in the universe");
+        return Ok(());
+    }
 
-                // Destroy the old package
-                place.dom.destroy(work.package_link_group);
+    println!(
+        "
+:: {} saved file(s) belong to places no longer in the universe:",
+        stale.len()
+    );
+    for path in &stale {
+        println!("> {}", path.display());
+    }
+    if !yes {
+        if !interactive {
+            println!(
+                ":: Not deleting them since stdin isn't a terminal, pass --yes to prune without asking"
+            );
+            return Ok(());
+        }
+        if !confirm(
+            rl,
+            interactive,
+            "
+:: Delete them? (yes/no)
+>> ",
+        )? {
+            println!("Pruning skipped.");
+            return Ok(());
+        }
+    }
 
-                // Transfer package contents into the place DOM under the same parent
-                package_dom.transfer(package_root, &mut place.dom, work.package_link_parent);
+    for path in &stale {
+        tokio::fs::remove_file(path)
+            .await
+            .with_context(|| format!("Failed to delete {}", path.display()))?;
+    }
+    println!(
+        ":: Pruned {} file(s) from the output directory",
+        stale.len()
+    );
+    Ok(())
+}
 
-                replacements += 1;
-            } else {
-                let msg = format!(
-                    "No fetched asset for package {} referenced in place {} {} - leaving untouched.",
-                    work.package_id_numbers, place.name, place.id
-                );
-                let _ = failed_tx.send(msg);
-                continue;
+// Exit statuses for automation. Fatal errors exit with 1 like any other Rust program
+const EXIT_FAILURES: u8 = 2;
+const EXIT_PUBLISH_FAILED: u8 = 3;
+const EXIT_INTERRUPTED: u8 = 4;
+
+// Asks whether to publish one place. Anything that isn't an answer asks again,
+// and a closed prompt aborts
+fn ask_publish(rl: &mut DefaultEditor, saved: &SavedPlace) -> PublishDecision {
+    progress::suspend(|| {
+        loop {
+            let prompt = format!(
+                "
+:: Publish {} (id: {}), {} PackageLink(s) replaced? (yes/no/abort)
+>> ",
+                saved.name, saved.id, saved.replacements
+            );
+            match rl
+                .readline(&prompt)
+                .map(|answer| answer.trim().to_lowercase())
+            {
+                Ok(answer) if answer == "yes" => return PublishDecision::Publish,
+                Ok(answer) if answer == "no" => return PublishDecision::Skip,
+                Ok(answer) if answer == "abort" => return PublishDecision::Abort,
+                Ok(_) => continue,
+                Err(_) => return PublishDecision::Abort,
             }
         }
+    })
+}
 
-        save_pb.set_message(format!(
-            "Serializing place {} ({}) with {} replacements",
-            place.name, place.id, replacements
-        ));
-        let mut buffer = Vec::new();
-        rbx_binary::to_writer(&mut buffer, &place.dom, place.dom.root().children())?;
+// Prompts for each credential and saves the ones given in the OS keyring
+fn store_credentials(rl: &mut DefaultEditor) -> Result<()> {
+    let api_key: SecretString = rl
+        .readline(
+            ":: Input Roblox API Key to store (leave empty to keep the stored one)
+>> ",
+        )?
+        .trim()
+        .to_string()
+        .into();
+    if !api_key.expose_secret().is_empty() {
+        keyring_store::store_api_key(&api_key)?;
+        println!(":: Stored the API key in the keyring");
+    }
 
-        save_pb.set_message(format!("Saving to /rbxls/{}.rbxl", place.id));
-        let folder = Path::new("rbxls");
-        tokio::fs::create_dir_all(folder).await?;
-        let file_path = folder.join(format!("{}.rbxl", place.id));
-        tokio::fs::write(&file_path, &buffer).await?;
+    let cookie: SecretString = rl
+        .readline(
+            ":: Input Roblox .ROBLOSECURITY to store (leave empty to keep the stored one)
+>> ",
+        )?
+        .trim()
+        .to_string()
+        .into();
+    if !cookie.expose_secret().is_empty() {
+        keyring_store::store_cookie(&cookie)?;
+        println!(":: Stored the .ROBLOSECURITY in the keyring");
+    }
 
-        saved_places.push(SavedPlace {
-            id: place.id,
-            name: place.name,
-            buffer,
-        });
+    Ok(())
+}
 
-        save_pb.inc(1);
+// Lists failures grouped by severity, worst first
+fn print_failures(heading: &str, failures: &[Failure]) {
+    if failures.is_empty() {
+        return;
     }
 
-    save_pb.finish_with_message("Saved all updated places locally (not published)");
-
-    Ok(saved_places)
-}
+    println!(
+        "
+{}:",
+        heading
+    );
+    for level in [
+        Severity::Error,
+        Severity::Warning,
+        Severity::Skipped,
+        Severity::Note,
+    ] {
+        let mut grouped: Vec<&Failure> = failures
+            .iter()
+            .filter(|failure| failure.level == level)
+            .collect();
+        grouped.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        if grouped.is_empty() {
+            continue;
+        }
 
-async fn publish_saved_places(
-    saved_places: Vec<SavedPlace>,
-    client: Arc<reqwest_middleware::ClientWithMiddleware>,
-    rbxl_api_key: String,
-    universe_id: u64,
-    spinner_style: ProgressStyle,
-    failed_tx: UnboundedSender<String>,
-) {
-    let publish_pb = ProgressBar::new(saved_places.len() as u64);
-    publish_pb.set_style(spinner_style.clone());
-    publish_pb.set_prefix("[publish]");
-
-    let publish_results = futures::stream::iter(saved_places.into_iter().map(|saved| {
-        let client = Arc::clone(&client);
-        let rbxl_api_key = rbxl_api_key.clone();
-        let publish_pb = publish_pb.clone();
-        let failed_tx = failed_tx.clone();
-        let universe_id = universe_id;
-        async move {
-            publish_pb.set_message(format!("Publishing place {} ({})", saved.name, saved.id));
-            let publish_response = client
-                .post(format!("https://apis.roblox.com/universes/v1/{}/places/{}/versions?versionType=Published", universe_id, saved.id))
-                .header("x-api-key", rbxl_api_key)
-                .header("Content-Type", "application/octet-stream")
-                .header("Content-Length", saved.buffer.len())
-                .body(saved.buffer)
-                .send()
-                .await;
-
-            match publish_response {
-                Ok(r) => {
-                    if r.status().is_success() {
-                        publish_pb.inc(1);
-                        Ok(saved.id)
-                    } else {
-                        let msg = format!("Failed to publish place {} {}: HTTP {}", saved.name, saved.id, r.status());
-                        let _ = failed_tx.send(msg);
-                        publish_pb.inc(1);
-                        Err(saved.id)
-                    }
-                }
-                Err(e) => {
-                    let msg = format!("Failed to publish place {} {}: {}", saved.name, saved.id, e);
-                    let _ = failed_tx.send(msg);
-                    publish_pb.inc(1);
-                    Err(saved.id)
-                }
-            }
+        let title = format!("{} ({})", level.label(), grouped.len());
+        let title = match level {
+            Severity::Error => style(title).red().bold(),
+            Severity::Warning => style(title).yellow().bold(),
+            Severity::Skipped => style(title).cyan(),
+            Severity::Note => style(title).dim(),
+        };
+        println!("{}", title);
+        for failure in grouped {
+            println!("- {}", failure);
         }
-    }))
-    .buffer_unordered(3)
-    .collect::<Vec<Result<u64, u64>>>()
-    .await;
+    }
+}
 
-    publish_pb.finish_and_clear();
+// Publish failures take priority over everything else that went wrong. Skips
+// and notes are expected, so only errors and warnings fail the run
+fn exit_code(failures: &[Failure], publish_failed: bool) -> ExitCode {
+    if publish_failed {
+        ExitCode::from(EXIT_PUBLISH_FAILED)
+    } else if failures
+        .iter()
+        .any(|failure| failure.level <= Severity::Warning)
+    {
+        ExitCode::from(EXIT_FAILURES)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
 
-    let total = publish_results.len();
-    let succeeded = publish_results.iter().filter(|r| r.is_ok()).count();
-    let failed = total - succeeded;
+// Stops after a Ctrl-C or --timeout, still showing everything collected so far
+async fn exit_interrupted(
+    interruption: Interruption,
+    updater: Updater,
+    mut failed_rx: UnboundedReceiver<Failure>,
+) -> Result<ExitCode> {
     println!(
-        "Publishing complete: {} succeeded, {} failed (out of {})",
-        succeeded, failed, total
+        "
+:: {}, stopping remaining work",
+        interruption
     );
+
+    drop(updater);
+    let mut failures: Vec<Failure> = Vec::new();
+    while let Some(msg) = failed_rx.recv().await {
+        failures.push(msg);
+    }
+
+    print_failures("Failures / warnings encountered before stopping", &failures);
+
+    // Stopping at an error is a failed run, not an interrupted one
+    match interruption {
+        Interruption::FailFast(_) => Ok(ExitCode::from(EXIT_FAILURES)),
+        _ => Ok(ExitCode::from(EXIT_INTERRUPTED)),
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Read environment variables from .env
+fn main() -> Result<ExitCode> {
+    // Read environment variables from .env, RBXL_THREADS included
     dotenv::dotenv().ok();
 
-    // Set up rustyline
+    // The worker count has to be known before the runtime exists, so it's read
+    // from the arguments by hand
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(cli::worker_threads()?)
+        .enable_all()
+        .build()?
+        .block_on(run())
+}
+
+async fn run() -> Result<ExitCode> {
+    // RUST_LOG picks what gets logged, everything at info and above by default
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(
+            fmt::layer()
+                .with_writer(ProgressWriter)
+                .without_time()
+                .with_target(false),
+        )
+        .init();
+
+    let args = cli::parse_args()?;
+    if args.config.max_places_in_memory.is_some()
+        && (args.csv.is_some() || args.config.max_download_mb.is_some())
+    {
+        bail!(
+            "--csv and --max-download-mb need every place at once, they can't be used with --max-places-in-memory"
+        );
+    }
+    if args.output_manifest.as_deref()
+        == Some(&Path::new(&args.config.output_dir).join("manifest.json"))
+    {
+        bail!(
+            "--output-manifest can't be {}/manifest.json, that file tracks what was last published",
+            args.config.output_dir
+        );
+    }
+    if args.universe_id.len() > 1 && !args.report_unused_packages {
+        bail!("Only --report-unused-packages takes more than one --universe-id");
+    }
+    if args.report_unused_packages && (args.command.is_some() || args.config.offline) {
+        bail!(
+            "--report-unused-packages scans live universes on its own, it can't be used with a command or --offline"
+        );
+    }
+    let scan_only = matches!(args.command, Some(Command::Scan));
+    let offline = args.config.offline;
+    // Nothing gets published offline, the saved places are the end result
+    let publishes = args.command.is_none() && !offline;
+
+    // Set up rustyline. Confirmations are skipped when there's no one to ask,
+    // like in CI or when piped
     let mut rl = DefaultEditor::new()?;
+    let interactive = std::io::stdin().is_terminal();
+    if args.interactive_publish && !interactive {
+        bail!("--interactive-publish needs stdin to be a terminal");
+    }
+
+    if let Some(Command::StoreCredentials) = args.command {
+        store_credentials(&mut rl)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
     let mut rbxl_api_key: String = dotenv::var("RBXL_API_KEY").unwrap_or("".to_string());
-    let mut rbxl_cookie: String = dotenv::var("RBXL_COOKIE").unwrap_or("".to_string());
+    let mut rbxl_cookie: SecretString = dotenv::var("RBXL_COOKIE").unwrap_or_default().into();
+
+    // The keyring only fills in what the environment left out
+    if args.use_keyring {
+        if rbxl_api_key.is_empty()
+            && let Some(api_key) = keyring_store::load_api_key()?
+        {
+            rbxl_api_key = api_key.expose_secret().to_string();
+        }
+        if rbxl_cookie.expose_secret().is_empty()
+            && let Some(cookie) = keyring_store::load_cookie()?
+        {
+            rbxl_cookie = cookie;
+        }
+    }
+
+    if rbxl_api_key.is_empty()
+        && let Some(api_key_file) = &args.api_key_file
+    {
+        rbxl_api_key = std::fs::read_to_string(api_key_file)?.trim().to_string();
+    }
 
-    if rbxl_api_key.is_empty() {
+    // Checks the credentials as found so far, without prompting for any
+    if let Some(Command::Doctor) = args.command {
+        if rbxl_cookie.expose_secret().is_empty()
+            && let Some(cookie_cache) = &args.cookie_cache
+            && let Some(cached_cookie) = load_cached_cookie(Path::new(cookie_cache))?
+        {
+            rbxl_cookie = cached_cookie;
+        }
+        let cookie = Some(&rbxl_cookie).filter(|cookie| !cookie.expose_secret().is_empty());
+
+        let client = Updater::build_plain_client(&args.config)?;
+        let checks = doctor::run_checks(&client, &args.config, &rbxl_api_key, cookie).await;
+        print_checks(&checks);
+        return Ok(if checks.iter().all(Check::passed) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(EXIT_FAILURES)
+        });
+    }
+    // Only publishing needs the API key, unless downloads use it too
+    if rbxl_api_key.is_empty() && (publishes || (args.use_api_key_auth && !offline)) {
         rbxl_api_key = rl.readline(
             ":: Input Roblox API Key
 >> ",
         )?;
     }
-    if rbxl_cookie.is_empty() {
+    // Reuse the cached cookie while it still works
+    let cookie_cache = args.cookie_cache.as_deref().map(Path::new);
+    if rbxl_cookie.expose_secret().is_empty()
+        && !offline
+        && !args.use_api_key_auth
+        && !args.refresh_cookie
+        && let Some(cookie_cache) = cookie_cache
+        && let Some(cached_cookie) = load_cached_cookie(cookie_cache)?
+    {
+        let client = Updater::build_plain_client(&args.config)?;
+        match validate_cookie(&client, &args.config.endpoints, &cached_cookie).await {
+            Ok(true) => {
+                println!(":: Using the cached .ROBLOSECURITY");
+                rbxl_cookie = cached_cookie;
+            }
+            Ok(false) => println!(":: The cached .ROBLOSECURITY has expired"),
+            Err(e) => println!(":: Couldn't validate the cached .ROBLOSECURITY: {}", e),
+        }
+    }
+    if rbxl_cookie.expose_secret().is_empty() && !offline && !args.use_api_key_auth {
         let auto_find_cookie_confirm = rl
             .readline(
                 "
@@ -581,180 +718,607 @@ async fn main() -> Result<()> {
             rbxl_cookie = get_roblosecurity()?;
             println!(":: Successfully retrieved .ROBLOSECURITY\n");
         } else {
-            rbxl_cookie = rl.readline(
-                ":: Input Roblox .ROBLOSECURITY
+            rbxl_cookie = rl
+                .readline(
+                    ":: Input Roblox .ROBLOSECURITY
 >> ",
-            )?;
+                )?
+                .into();
+        }
+
+        if let Some(cookie_cache) = cookie_cache {
+            save_cached_cookie(cookie_cache, &rbxl_cookie)?;
         }
     }
 
-    // Progress style
-    let spinner_style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
-        .unwrap()
-        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
+    // Failure collector
+    let (failed_tx, mut failed_rx): (UnboundedSender<Failure>, UnboundedReceiver<Failure>) =
+        tokio::sync::mpsc::unbounded_channel();
+    if let Some(log_file) = &args.log_file {
+        failed_rx = tee_to_log_file(failed_rx, Path::new(log_file)).await?;
+    }
+    // Without --fail-fast the sender is kept around unused, so no error ever arrives
+    let (_no_errors, mut first_error) = tokio::sync::watch::channel(None);
+    if args.fail_fast {
+        (failed_rx, first_error) = watch_first_error(failed_rx);
+    }
 
-    // Set up a client with exponential backoff
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    let jar = Jar::default();
-    jar.add_cookie_str(
-        &format!(".ROBLOSECURITY={rbxl_cookie}"),
-        &"https://assetdelivery.roblox.com".parse::<Url>().unwrap(),
+    let updater = if args.use_api_key_auth {
+        println!(":: Downloading assets with the Open Cloud API key");
+        let client = Updater::build_client(&args.config, None)?;
+        Updater::new(client, args.config.clone(), failed_tx)
+            .with_asset_auth(AssetAuth::ApiKey(rbxl_api_key.clone()))
+    } else {
+        let client = Updater::build_client(&args.config, Some(&rbxl_cookie))?;
+        Updater::new(client, args.config.clone(), failed_tx)
+    };
+    // The cookie jar has its own copy now, so wipe ours instead of keeping it around
+    drop(rbxl_cookie);
+    println!(
+        ":: Concurrency: {} package download(s), {} publish(es)",
+        args.config.download_concurrency, args.config.publish_concurrency
     );
-    let cookies = Arc::new(jar);
-    let client = ClientBuilder::new(
-        reqwest::Client::builder()
-            .cookie_provider(Arc::clone(&cookies))
-            .timeout(std::time::Duration::from_secs(20))
-            .build()?,
-    )
-    .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-    .build();
-
-    // Prompt for UniverseId
-    let mut universe_id: String = dotenv::var("RBXL_UNIVERSE_ID").unwrap_or("".to_string());
-    if universe_id.is_empty() {
-        universe_id = rl.readline(
-            ":: Input Universe Id
->> ",
-        )?;
+
+    if let Some(Command::Inspect {
+        package_id,
+        properties,
+    }) = &args.command
+    {
+        let dom = updater.fetch_package_dom(package_id).await?;
+        println!("\nPackage {}:", package_id);
+        for referent in dom.root().children() {
+            print_instance_tree(&dom, *referent, 0);
+        }
+        if *properties {
+            print_properties(&dom);
+        }
+        return Ok(ExitCode::SUCCESS);
     }
-    let universe_id = universe_id.trim().parse()?;
-    let client = Arc::new(client);
 
-    // Failure collector
-    let (failed_tx, mut failed_rx): (UnboundedSender<String>, UnboundedReceiver<String>) =
-        tokio::sync::mpsc::unbounded_channel();
+    if let Some(Command::Diff { verbose }) = args.command {
+        let diffs = updater.diff_saved_places().await?;
+        print_place_diffs(&diffs, verbose);
 
-    // Collect places and package ids
-    let places_data = collect_places_and_package_ids(
-        Arc::clone(&client),
-        universe_id,
-        spinner_style.clone(),
-        failed_tx.clone(),
-    )
-    .await?;
+        drop(updater);
+        let mut failures: Vec<Failure> = Vec::new();
+        while let Some(msg) = failed_rx.recv().await {
+            failures.push(msg);
+        }
+        print_failures("Failures / warnings encountered during diffing", &failures);
+        return Ok(exit_code(&failures, false));
+    }
+
+    // Only scans, then reports where each package is used across the universes
+    if args.report_unused_packages {
+        if args.universe_id.is_empty() {
+            bail!("--report-unused-packages needs at least one --universe-id");
+        }
+        let mut usage = CrossUniverseUsage::default();
+        for &universe_id in &args.universe_id {
+            println!(":: Scanning universe {}", universe_id);
+            let places_data = updater.collect_places_and_package_ids(universe_id).await?;
+            usage.add_universe(universe_id, &places_data);
+        }
+        print_cross_universe_usage(&usage);
 
-    // Build unique package set
-    let mut unique_packages: HashSet<String> = HashSet::new();
-    for p in &places_data {
-        for w in &p.to_work {
-            unique_packages.insert(w.package_id_numbers.clone());
+        drop(updater);
+        let mut failures: Vec<Failure> = Vec::new();
+        while let Some(msg) = failed_rx.recv().await {
+            failures.push(msg);
         }
+        print_failures("Failures / warnings encountered during scanning", &failures);
+        return Ok(exit_code(&failures, false));
     }
 
-    println!(
-        "Found {} unique package ids to fetch",
-        unique_packages.len()
-    );
+    // Use the given UniverseId, resolve it from a place, or prompt for it
+    // Offline the places come from --places-dir, so no universe is needed
+    let universe_id: u64 = match (args.universe_id.first().copied(), args.place_id) {
+        (Some(universe_id), _) => universe_id,
+        _ if offline => 0,
+        (None, Some(place_id)) => {
+            let universe_id = updater.resolve_universe_id(place_id).await?;
+            println!(":: Place {} belongs to universe {}", place_id, universe_id);
+            universe_id
+        }
+        (None, None) => rl
+            .readline(
+                ":: Input Universe Id
+>> ",
+            )?
+            .trim()
+            .parse()?,
+    };
 
-    // Fetch package assets
-    let packages_vec: Vec<String> = unique_packages.into_iter().collect();
-    let package_bytes_map = fetch_package_assets(
-        Arc::clone(&client),
-        packages_vec,
-        spinner_style.clone(),
-        failed_tx.clone(),
-    )
-    .await;
-
-    // Process places and save locally
-    let saved_places = process_places_and_save(
-        places_data,
-        package_bytes_map,
-        spinner_style.clone(),
-        failed_tx.clone(),
-    )
-    .await?;
+    // Group-owned universes need an API key with access to the group's experience
+    if offline {
+        println!(
+            ":: Offline: reading places from {} and packages from {}",
+            args.config.places_dir.as_deref().unwrap_or("."),
+            args.config.packages_dir.as_deref().unwrap_or(".")
+        );
+    } else {
+        match updater.fetch_universe(universe_id).await {
+            Ok(universe) if universe.creator_type() == "Group" => println!(
+                ":: Universe {} is owned by the group {} ({}). Publishing needs an API key created by the group, or one given access to this experience",
+                universe.name(),
+                universe.creator_name(),
+                universe.creator_target_id()
+            ),
+            Ok(universe) => println!(
+                ":: Universe {} is owned by the user {} ({})",
+                universe.name(),
+                universe.creator_name(),
+                universe.creator_target_id()
+            ),
+            Err(e) => println!(
+                ":: Couldn't look up who owns universe {}: {}",
+                universe_id, e
+            ),
+        }
+    }
+
+    // Every phase below can be cut short by Ctrl-C or --timeout
+    let started = tokio::time::Instant::now();
+    let timeout = args.timeout.map(Duration::from_secs);
+
+    // With --max-places-in-memory each batch of places is scanned, updated and
+    // saved before the next one is downloaded, so only the saved places are left
+    let batch_size = args.config.max_places_in_memory.filter(|_| !scan_only);
+    let (saved_places, unfetched_packages, broken_references, package_usage) =
+        if let Some(batch_size) = batch_size {
+            let update = match until_interrupted(
+                updater.update_in_batches(universe_id, batch_size),
+                started,
+                timeout,
+                &first_error,
+            )
+            .await
+            {
+                Ok(update) => update?,
+                Err(interruption) => {
+                    return exit_interrupted(interruption, updater, failed_rx).await;
+                }
+            };
+            if args.stats {
+                print_package_stats(&update.package_stats);
+            }
+            (
+                update.saved_places,
+                update.unfetched_packages,
+                update.broken_references,
+                None,
+            )
+        } else {
+            // Collect places and package ids
+            let places_data = match until_interrupted(
+                updater.collect_places_and_package_ids(universe_id),
+                started,
+                timeout,
+                &first_error,
+            )
+            .await
+            {
+                Ok(places_data) => places_data?,
+                Err(interruption) => {
+                    return exit_interrupted(interruption, updater, failed_rx).await;
+                }
+            };
+
+            if scan_only {
+                print_package_links(&places_data);
+
+                drop(updater);
+                let mut failures: Vec<Failure> = Vec::new();
+                while let Some(msg) = failed_rx.recv().await {
+                    failures.push(msg);
+                }
+                print_failures("Failures / warnings encountered during scanning", &failures);
+                return Ok(exit_code(&failures, false));
+            }
+
+            // Nothing was listed, or every place was skipped or failed, so there's
+            // nothing to fetch packages for or publish
+            if places_data.is_empty() {
+                println!(":: No places to update");
+
+                drop(updater);
+                let mut failures: Vec<Failure> = Vec::new();
+                while let Some(msg) = failed_rx.recv().await {
+                    failures.push(msg);
+                }
+                print_failures("Failures / warnings encountered during scanning", &failures);
+                return Ok(exit_code(&failures, false));
+            }
+
+            let package_ids = unique_package_ids(&places_data);
+            if updater.config().packages.is_empty() {
+                println!("Found {} unique package ids to fetch", package_ids.len());
+            } else {
+                let selected = package_ids
+                    .iter()
+                    .filter(|id| updater.is_package_selected(id))
+                    .count();
+                println!(
+                    "Found {} unique package ids, fetching the {} selected by --packages",
+                    package_ids.len(),
+                    selected
+                );
+            }
+
+            // Huge universes can mean gigabytes of packages, so check before pulling them
+            if let Some(max_download_mb) = args.config.max_download_mb {
+                let (estimated_bytes, unknown) = match until_interrupted(
+                    updater.estimate_package_download_size(&package_ids),
+                    started,
+                    timeout,
+                    &first_error,
+                )
+                .await
+                {
+                    Ok(estimate) => estimate?,
+                    Err(interruption) => {
+                        return exit_interrupted(interruption, updater, failed_rx).await;
+                    }
+                };
+                let estimated_mb = estimated_bytes as f64 / (1024.0 * 1024.0);
+                if unknown > 0 {
+                    println!(
+                        ":: Estimated download size: {:.1} MB ({} package size(s) unknown)",
+                        estimated_mb, unknown
+                    );
+                } else {
+                    println!(":: Estimated download size: {:.1} MB", estimated_mb);
+                }
+
+                if estimated_mb > max_download_mb as f64 {
+                    let download_confirm = confirm(
+                        &mut rl,
+                        interactive,
+                        &format!(
+                            ":: That's more than --max-download-mb {}, download anyway? (yes/no)
+>> ",
+                            max_download_mb
+                        ),
+                    )?;
+                    if !download_confirm {
+                        println!("Download skipped, nothing was changed.");
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                }
+            }
+
+            // Fetch package assets, honouring and updating the lockfile
+            let (package_bytes_map, package_stats, broken_packages) = match until_interrupted(
+                updater.fetch_locked_package_assets(package_ids),
+                started,
+                timeout,
+                &first_error,
+            )
+            .await
+            {
+                Ok(fetched) => fetched?,
+                Err(interruption) => {
+                    return exit_interrupted(interruption, updater, failed_rx).await;
+                }
+            };
+
+            if args.stats {
+                print_package_stats(&package_stats);
+            }
+            // Packages left out by --packages were never meant to be fetched, and
+            // broken ones get a list of their own
+            let mut unfetched_packages = missing_packages(&places_data, &package_bytes_map);
+            unfetched_packages.retain(|package_id, _| {
+                updater.is_package_selected(package_id) && !broken_packages.contains_key(package_id)
+            });
+            let broken_references = broken_references(&places_data, &broken_packages);
+
+            let package_hashes: HashMap<String, String> = package_stats
+                .into_iter()
+                .map(|stats| (stats.id, stats.sha256))
+                .collect();
+
+            // Processing consumes the places, so note what they use beforehand
+            let package_usage = args.csv.as_ref().map(|_| package_usage(&places_data));
+
+            // Process places and save locally
+            let saved_places = match until_interrupted(
+                updater.process_places_and_save(
+                    places_data,
+                    package_bytes_map,
+                    &package_hashes,
+                    &broken_packages,
+                ),
+                started,
+                timeout,
+                &first_error,
+            )
+            .await
+            {
+                Ok(saved_places) => saved_places?,
+                Err(interruption) => {
+                    return exit_interrupted(interruption, updater, failed_rx).await;
+                }
+            };
+
+            (
+                saved_places,
+                unfetched_packages,
+                broken_references,
+                package_usage,
+            )
+        };
 
     // Drain any immediate failures so far. We'll collect all later too.
-    let mut early_failures: Vec<String> = Vec::new();
+    let mut early_failures: Vec<Failure> = Vec::new();
     while let Ok(msg) = failed_rx.try_recv() {
         early_failures.push(msg);
     }
 
-    if !early_failures.is_empty() {
+    print_failures(
+        "Failures / warnings encountered during scanning/fetching/replacement",
+        &early_failures,
+    );
+
+    if let (Some(csv), Some(package_usage)) = (&args.csv, &package_usage) {
+        write_usage_csv(
+            csv,
+            package_usage,
+            &saved_places,
+            &early_failures,
+            |package_id| updater.is_package_selected(package_id),
+        )?;
+        println!(":: Wrote package usage to {}", csv.display());
+    }
+    // Written before publishing, so it's there whether or not anything is published
+    if let Some(output_manifest) = &args.output_manifest {
+        write_output_manifest(output_manifest, &saved_places).await?;
+        println!(
+            ":: Wrote the list of saved places to {}",
+            output_manifest.display()
+        );
+    }
+
+    // Files of places that left the universe would otherwise stay around forever
+    if args.prune_output {
+        prune_output(&updater, universe_id, &mut rl, interactive, args.yes).await?;
+    }
+
+    // Show exactly what would change before asking to publish
+    let changed_places: Vec<&SavedPlace> = saved_places
+        .iter()
+        .filter(|saved| saved.replacements > 0 || saved.resumed)
+        .collect();
+    if changed_places.is_empty() {
+        println!(
+            "
+No PackageLinks were replaced in any place."
+        );
+    } else {
         println!(
             "
-Failures / warnings encountered during scanning/fetching/replacement:"
+Places that will change:"
         );
-        for s in early_failures.iter() {
-            println!("- {}", s);
+        for saved in changed_places {
+            if saved.resumed {
+                println!(
+                    "> {} (id: {}): resumed from a previous run",
+                    saved.name, saved.id
+                );
+                continue;
+            }
+            println!(
+                "> {} (id: {}): {} PackageLink(s) replaced, instances: {}, packages: {}",
+                saved.name,
+                saved.id,
+                saved.replacements,
+                instance_delta(saved),
+                saved
+                    .replaced_packages
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
         }
     }
 
     // Now wait for user permission to publish all saved places
-    let publish_confirm = rl
-        .readline(
-            "
+    // With --interactive-publish each place is asked about on its own instead
+    let publish_confirm = publishes
+        && match (args.publish, args.no_publish) {
+            (true, _) => true,
+            _ if args.interactive_publish => true,
+            (_, true) => false,
+            _ if !interactive => {
+                println!(
+                    ":: Not publishing since stdin isn't a terminal, pass --publish to publish without asking"
+                );
+                false
+            }
+            _ => confirm(
+                &mut rl,
+                interactive,
+                "
 :: Publish all saved places now? (yes/no)
 >> ",
-        )?
-        .to_lowercase()
-        == "yes";
+            )?,
+        };
     if !publish_confirm {
-        println!("Publishing skipped. Local files are available under ./rbxls/*.rbxl");
+        println!(
+            "Publishing skipped. Local files are available under {}",
+            Path::new(&args.config.output_dir)
+                .join(if args.config.compress {
+                    "*.rbxl.gz"
+                } else {
+                    "*.rbxl"
+                })
+                .display()
+        );
 
         // Drain remaining messages so user can inspect them
-        drop(failed_tx);
-        let mut remaining: Vec<String> = Vec::new();
+        drop(updater);
+        let mut remaining: Vec<Failure> = early_failures;
+        let already_shown = remaining.len();
         while let Some(msg) = failed_rx.recv().await {
             remaining.push(msg);
         }
 
-        if !remaining.is_empty() {
-            println!(
-                "
-Additional failures captured:"
-            );
-            for s in remaining.iter() {
-                println!("- {}", s);
+        print_failures("Additional failures captured", &remaining[already_shown..]);
+        print_missing_packages(&unfetched_packages);
+        print_broken_references(&broken_references);
+
+        if interactive {
+            rl.readline(
+                ":: Press enter to exit
+>> ",
+            )?;
+        }
+        return Ok(exit_code(&remaining, false));
+    }
+
+    // Keep a copy of what's live before overwriting it
+    let saved_places = if args.config.backup {
+        match until_interrupted(
+            updater.backup_live_places(saved_places),
+            started,
+            timeout,
+            &first_error,
+        )
+        .await
+        {
+            Ok(backed_up) => backed_up?,
+            Err(interruption) => return exit_interrupted(interruption, updater, failed_rx).await,
+        }
+    } else {
+        saved_places
+    };
+
+    // Publish, then offer to retry whatever failed without redoing everything else
+    let mut to_publish = saved_places;
+    let mut gave_up = false;
+    let mut conflicts_retried = false;
+    let publish_failed = loop {
+        let (publish_results, failed_places) = match until_interrupted(
+            updater.publish_saved_places_with(
+                to_publish,
+                &rbxl_api_key,
+                universe_id,
+                args.interactive_publish
+                    .then_some(|saved: &SavedPlace| ask_publish(&mut rl, saved)),
+            ),
+            started,
+            timeout,
+            &first_error,
+        )
+        .await
+        {
+            Ok(published) => published,
+            Err(interruption) => return exit_interrupted(interruption, updater, failed_rx).await,
+        };
+        print_publish_results(&publish_results, args.config.version_type);
+
+        // Publishing a stale place again would overwrite someone else's changes,
+        // so conflicts are either updated again first or left for the next run
+        let conflicted_ids: HashSet<u64> = publish_results
+            .iter()
+            .filter(|result| result.conflict)
+            .map(|result| result.id)
+            .collect();
+        let (conflicted, failed_places): (Vec<SavedPlace>, Vec<SavedPlace>) = failed_places
+            .into_iter()
+            .partition(|saved| conflicted_ids.contains(&saved.id));
+
+        let mut next_round: Vec<SavedPlace> = Vec::new();
+        if !conflicted.is_empty() {
+            if args.retry_conflicts && !conflicts_retried {
+                conflicts_retried = true;
+                println!(
+                    ":: {} place(s) were published by someone else meanwhile, updating them again on top of the live version",
+                    conflicted.len()
+                );
+                let stale = conflicted.len();
+                next_round = match until_interrupted(
+                    updater.update_places_again(conflicted),
+                    started,
+                    timeout,
+                    &first_error,
+                )
+                .await
+                {
+                    Ok(updated) => updated?,
+                    Err(interruption) => {
+                        return exit_interrupted(interruption, updater, failed_rx).await;
+                    }
+                };
+                gave_up |= next_round.len() < stale;
+            } else {
+                println!(
+                    ":: {} place(s) were published by someone else meanwhile and were left alone. Run again{} to update them on top of the live version",
+                    conflicted.len(),
+                    if args.retry_conflicts {
+                        ""
+                    } else {
+                        " or pass --retry-conflicts"
+                    }
+                );
+                gave_up = true;
             }
         }
 
-        rl.readline(
-            ":: Press enter to exit
+        if !failed_places.is_empty() {
+            let retry_confirm = confirm(
+                &mut rl,
+                interactive,
+                &format!(
+                    "
+:: Retry publishing the {} failed place(s)? (yes/no)
 >> ",
-        )?;
-        return Ok(());
-    }
+                    failed_places.len()
+                ),
+            )?;
+            if retry_confirm {
+                next_round.extend(failed_places);
+            } else {
+                gave_up = true;
+            }
+        }
 
-    // Publish
-    publish_saved_places(
-        saved_places,
-        Arc::clone(&client),
-        rbxl_api_key,
-        universe_id,
-        spinner_style.clone(),
-        failed_tx.clone(),
-    )
-    .await;
+        if next_round.is_empty() {
+            break gave_up;
+        }
+        to_publish = next_round;
+    };
 
     // After publishing, collect all failure messages from channel and display it if there are any
-    drop(failed_tx);
-    let mut failures: Vec<String> = Vec::new();
+    drop(updater);
+    let mut failures: Vec<Failure> = Vec::new();
     while let Some(msg) = failed_rx.recv().await {
         failures.push(msg);
     }
 
     if !failures.is_empty() {
-        println!(
-            "
-Failures / warnings encountered during operation:"
+        print_failures(
+            "Failures / warnings encountered during operation",
+            &failures,
         );
-        for s in failures.iter() {
-            println!("- {}", s);
-        }
     } else {
         println!(
             "
 All operations completed successfully."
         );
     }
+    print_missing_packages(&unfetched_packages);
+    print_broken_references(&broken_references);
 
-    rl.readline(
-        ":: Press enter to exit
+    if interactive {
+        rl.readline(
+            ":: Press enter to exit
 >> ",
-    )?;
+        )?;
+    }
 
-    Ok(())
+    // The early failures were already shown, but they still make the run a failure
+    failures.extend(early_failures);
+    Ok(exit_code(&failures, publish_failed))
 }
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::cancel::write_atomically;
+
+/// What each place looked like the last time it was published, so a later run
+/// can tell when neither the place nor any of its packages changed since.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub places: BTreeMap<u64, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// SHA-256 of the place as it was downloaded
+    pub source_sha256: String,
+    /// SHA-256 of the place as it was saved. Once that version is published it's
+    /// what the next run downloads
+    pub saved_sha256: String,
+    /// SHA-256 of every package that was replaced in the place
+    pub packages: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    // A missing manifest is the same as an empty one
+    pub async fn load(path: &Path) -> Result<Manifest> {
+        if !tokio::fs::try_exists(path).await? {
+            return Ok(Manifest::default());
+        }
+
+        let contents = tokio::fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)? + "\n";
+        write_atomically(path, contents.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Whether a place downloaded with `place_sha256` and using these package
+    /// contents would come out the same as the last time it was saved
+    pub fn is_unchanged(
+        &self,
+        place_id: u64,
+        place_sha256: &str,
+        packages: &BTreeMap<String, String>,
+    ) -> bool {
+        self.places.get(&place_id).is_some_and(|entry| {
+            (entry.source_sha256 == place_sha256 || entry.saved_sha256 == place_sha256)
+                && &entry.packages == packages
+        })
+    }
+}
@@ -0,0 +1,9 @@
+use getset::Getters;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Getters, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct OpenCloudAssetResponse {
+    pub location: String,
+}
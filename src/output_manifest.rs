@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::{SavedPlace, cancel::write_atomically};
+
+#[derive(Serialize)]
+struct OutputManifest<'a> {
+    places: Vec<OutputEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct OutputEntry<'a> {
+    id: u64,
+    name: &'a str,
+    path: String,
+    /// Size of the file as it is on disk, compressed or not
+    bytes: u64,
+    /// SHA-256 of the file as it is on disk
+    sha256: String,
+}
+
+/// Writes a JSON index of every saved place file: its place id and name, path,
+/// size and SHA-256. The files are read back from disk for this, so the hashes
+/// are of what's actually there, `--compress` and `--resume` included
+pub async fn write_output_manifest(path: &Path, saved_places: &[SavedPlace]) -> Result<()> {
+    // By place id, so the manifests of two runs can be diffed
+    let mut sorted: Vec<&SavedPlace> = saved_places.iter().collect();
+    sorted.sort_by_key(|saved| saved.id);
+
+    let mut places = Vec::new();
+    for saved in sorted {
+        let contents = tokio::fs::read(&saved.path)
+            .await
+            .with_context(|| format!("Failed to read {}", saved.path.display()))?;
+        places.push(OutputEntry {
+            id: saved.id,
+            name: &saved.name,
+            path: saved.path.display().to_string(),
+            bytes: contents.len() as u64,
+            sha256: hex::encode(Sha256::digest(&contents)),
+        });
+    }
+
+    let contents = serde_json::to_string_pretty(&OutputManifest { places })? + "\n";
+    write_atomically(path, contents.as_bytes())
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
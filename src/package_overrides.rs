@@ -0,0 +1,195 @@
+use rbx_dom_weak::{Ustr, WeakDom};
+use rbx_types::{Attributes, Ref, Tags, Variant};
+use std::collections::HashMap;
+
+/// Local edits captured from an instanced package before it gets replaced.
+///
+/// The package root always keeps what belongs to that particular copy rather
+/// than the package itself: its name, where it's placed (`CFrame`, pivot) and
+/// its Attributes and CollectionService tags. Attributes and tags are merged
+/// rather than replaced, so ones the new version added are kept alongside the
+/// local ones. Anything else on the root, like a script's `Source` or a Part's
+/// `Size`, comes from the new version. When the root's class changed upstream
+/// only the name is kept.
+///
+/// Descendants are matched by name path and class, and are only restored when
+/// asked for, since without the previous package version there is no way to
+/// tell a local edit apart from an upstream change to the package. These
+/// divergences can't be preserved at all:
+/// - `Ref` properties (e.g. `PrimaryPart`), as the old referents are destroyed
+/// - instances whose path is ambiguous because siblings share a name
+/// - instances that were added, removed or renamed locally, or that no longer
+///   exist in the new package
+pub struct PackageOverrides {
+    root_name: String,
+    root_class: Ustr,
+    root_properties: Vec<(Ustr, Variant)>,
+    descendants: HashMap<String, Option<CapturedInstance>>,
+}
+
+struct CapturedInstance {
+    class: Ustr,
+    properties: Vec<(Ustr, Variant)>,
+}
+
+// Properties of the package root that belong to the placed copy
+const ROOT_PROPERTIES: &[&str] = &[
+    "CFrame",
+    "WorldPivotData",
+    "PivotOffset",
+    "Attributes",
+    "Tags",
+];
+
+fn copyable_properties(dom: &WeakDom, referent: Ref) -> Vec<(Ustr, Variant)> {
+    dom.get_by_ref(referent)
+        .map(|instance| {
+            instance
+                .properties
+                .iter()
+                .filter(|(_, value)| !matches!(value, Variant::Ref(_)))
+                .map(|(key, value)| (*key, value.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Maps each descendant's name path (relative to `root`) to its referent. Paths
+// shared by more than one instance map to None since they can't be matched.
+fn index_by_path(dom: &WeakDom, root: Ref) -> HashMap<String, Option<Ref>> {
+    let mut paths: HashMap<String, Option<Ref>> = HashMap::new();
+    let mut stack: Vec<(Ref, String)> = vec![(root, String::new())];
+
+    while let Some((referent, path)) = stack.pop() {
+        let Some(instance) = dom.get_by_ref(referent) else {
+            continue;
+        };
+        for &child_ref in instance.children() {
+            let Some(child) = dom.get_by_ref(child_ref) else {
+                continue;
+            };
+            if child.class == "PackageLink" {
+                continue;
+            }
+            let child_path = format!("{}/{}", path, child.name);
+            paths
+                .entry(child_path.clone())
+                .and_modify(|existing| *existing = None)
+                .or_insert(Some(child_ref));
+            stack.push((child_ref, child_path));
+        }
+    }
+
+    paths
+}
+
+impl PackageOverrides {
+    pub fn capture(dom: &WeakDom, package_root: Ref, include_descendants: bool) -> Self {
+        let (root_name, root_class) = dom
+            .get_by_ref(package_root)
+            .map(|instance| (instance.name.clone(), instance.class))
+            .unwrap_or_default();
+
+        let mut descendants = HashMap::new();
+        if include_descendants {
+            for (path, referent) in index_by_path(dom, package_root) {
+                let captured = referent.and_then(|referent| {
+                    dom.get_by_ref(referent).map(|instance| CapturedInstance {
+                        class: instance.class,
+                        properties: copyable_properties(dom, referent),
+                    })
+                });
+                descendants.insert(path, captured);
+            }
+        }
+
+        let root_properties = copyable_properties(dom, package_root)
+            .into_iter()
+            .filter(|(key, _)| ROOT_PROPERTIES.contains(&key.as_str()))
+            .collect();
+
+        PackageOverrides {
+            root_name,
+            root_class,
+            root_properties,
+            descendants,
+        }
+    }
+
+    /// Re-applies the captured values onto the freshly transferred package,
+    /// returning how many properties were changed.
+    pub fn apply(&self, dom: &mut WeakDom, package_root: Ref) -> usize {
+        let mut restored = 0;
+
+        if let Some(root) = dom.get_by_ref_mut(package_root) {
+            if root.name != self.root_name {
+                root.name = self.root_name.clone();
+                restored += 1;
+            }
+            // The old class's properties may not exist on the new one
+            if root.class == self.root_class {
+                restored += restore_properties(&mut root.properties, &self.root_properties);
+            }
+        }
+
+        if self.descendants.is_empty() {
+            return restored;
+        }
+
+        for (path, referent) in index_by_path(dom, package_root) {
+            let (Some(referent), Some(Some(captured))) = (referent, self.descendants.get(&path))
+            else {
+                continue;
+            };
+            if let Some(instance) = dom.get_by_ref_mut(referent)
+                && instance.class == captured.class
+            {
+                restored += restore_properties(&mut instance.properties, &captured.properties);
+            }
+        }
+
+        restored
+    }
+}
+
+fn restore_properties(
+    properties: &mut rbx_dom_weak::UstrMap<Variant>,
+    captured: &[(Ustr, Variant)],
+) -> usize {
+    let mut restored = 0;
+    for (key, value) in captured {
+        let value = match (properties.get(key), value) {
+            (Some(Variant::Attributes(new)), Variant::Attributes(old)) => {
+                Variant::Attributes(merge_attributes(new, old))
+            }
+            (Some(Variant::Tags(new)), Variant::Tags(old)) => Variant::Tags(merge_tags(new, old)),
+            _ => value.clone(),
+        };
+        if properties.get(key) != Some(&value) {
+            properties.insert(*key, value);
+            restored += 1;
+        }
+    }
+    restored
+}
+
+// Attributes the new version added are kept, and the local values win for the
+// ones both have
+fn merge_attributes(new: &Attributes, old: &Attributes) -> Attributes {
+    let mut merged = new.clone();
+    for (key, value) in old.iter() {
+        merged.insert(key.clone(), value.clone());
+    }
+    merged
+}
+
+// Every tag from either version, the new version's first
+fn merge_tags(new: &Tags, old: &Tags) -> Tags {
+    let mut merged = new.clone();
+    for tag in old.iter() {
+        if !new.iter().any(|existing| existing == tag) {
+            merged.push(tag);
+        }
+    }
+    merged
+}
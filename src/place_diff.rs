@@ -0,0 +1,93 @@
+use rbx_dom_weak::{Instance, WeakDom};
+use rbx_types::{Ref, Variant};
+use std::collections::BTreeMap;
+
+/// Instances that differ between two versions of a place, each given as
+/// `Path.To.Instance (Class)`
+#[derive(Debug, Default)]
+pub struct PlaceDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl PlaceDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Every instance keyed by its dotted path and class. Siblings sharing a name and
+// class end up in the same list, in child order.
+fn index_by_path(dom: &WeakDom) -> BTreeMap<String, Vec<&Instance>> {
+    let mut paths: BTreeMap<String, Vec<&Instance>> = BTreeMap::new();
+    let mut stack: Vec<(Ref, String)> = vec![(dom.root_ref(), String::new())];
+
+    while let Some((referent, path)) = stack.pop() {
+        let Some(instance) = dom.get_by_ref(referent) else {
+            continue;
+        };
+        for &child_ref in instance.children() {
+            let Some(child) = dom.get_by_ref(child_ref) else {
+                continue;
+            };
+            let child_path = if path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}.{}", path, child.name)
+            };
+            paths
+                .entry(format!("{} ({})", child_path, child.class))
+                .or_default()
+                .push(child);
+            stack.push((child_ref, child_path));
+        }
+    }
+
+    paths
+}
+
+// Refs point at referents that differ between files, so they can't be compared
+fn same_properties(old: &Instance, new: &Instance) -> bool {
+    let comparable = |instance: &Instance| {
+        instance
+            .properties
+            .iter()
+            .filter(|(_, value)| !matches!(value, Variant::Ref(_)))
+            .count()
+    };
+    comparable(old) == comparable(new)
+        && old.properties.iter().all(|(key, value)| {
+            matches!(value, Variant::Ref(_)) || new.properties.get(key) == Some(value)
+        })
+}
+
+/// Compares two versions of a place instance by instance. Instances are matched
+/// by path and class, so a renamed instance shows up as removed and added.
+pub fn diff_doms(old: &WeakDom, new: &WeakDom) -> PlaceDiff {
+    let old_paths = index_by_path(old);
+    let new_paths = index_by_path(new);
+    let mut diff = PlaceDiff::default();
+
+    for (path, old_instances) in old_paths.iter() {
+        let new_instances = new_paths.get(path).map(Vec::as_slice).unwrap_or_default();
+        for (index, old_instance) in old_instances.iter().enumerate() {
+            match new_instances.get(index) {
+                Some(new_instance) if !same_properties(old_instance, new_instance) => {
+                    diff.changed.push(path.clone())
+                }
+                Some(_) => {}
+                None => diff.removed.push(path.clone()),
+            }
+        }
+    }
+
+    for (path, new_instances) in new_paths.iter() {
+        let old_count = old_paths.get(path).map_or(0, Vec::len);
+        for _ in old_count..new_instances.len() {
+            diff.added.push(path.clone());
+        }
+    }
+
+    diff
+}
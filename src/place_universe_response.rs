@@ -0,0 +1,9 @@
+use getset::Getters;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Getters, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct PlaceUniverseResponse {
+    pub universe_id: u64,
+}
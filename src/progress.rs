@@ -0,0 +1,97 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    io::{self, Write},
+    sync::LazyLock,
+    time::Duration,
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+// Every progress bar lives here, so log lines can hide them while being written
+static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+// One line above all the others naming the phase the run is in. It's only
+// created once a phase starts, so commands that never run one don't show it
+static PHASE: LazyLock<ProgressBar> = LazyLock::new(|| {
+    let pb = PROGRESS.insert(0, ProgressBar::new_spinner());
+    pb.set_style(
+        ProgressStyle::with_template("{spinner:.bold} {msg:.bold}")
+            .unwrap()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ "),
+    );
+    pb.enable_steady_tick(Duration::from_millis(120));
+    pb
+});
+
+/// The phases of a run, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    CollectPlaces,
+    FetchPackages,
+    ProcessPlaces,
+    Publish,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [
+        Phase::CollectPlaces,
+        Phase::FetchPackages,
+        Phase::ProcessPlaces,
+        Phase::Publish,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::CollectPlaces => "Collecting places",
+            Phase::FetchPackages => "Fetching packages",
+            Phase::ProcessPlaces => "Replacing packages and saving",
+            Phase::Publish => "Publishing",
+        }
+    }
+}
+
+/// Shows `phase` on the line above the other progress bars, as "Phase 2/4:
+/// Fetching packages". With `--batch-size` the phases take turns per batch,
+/// so this can go back to an earlier one
+pub fn set_phase(phase: Phase) {
+    let number = Phase::ALL.iter().position(|p| *p == phase).unwrap_or(0) + 1;
+    PHASE.set_message(format!(
+        "Phase {}/{}: {}",
+        number,
+        Phase::ALL.len(),
+        phase.label()
+    ));
+}
+
+/// Shows a progress bar alongside the others
+pub fn add(pb: ProgressBar) -> ProgressBar {
+    PROGRESS.add(pb)
+}
+
+/// Hides the progress bars while `f` runs, so it can print or prompt
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    PROGRESS.suspend(f)
+}
+
+/// Writes tracing output to stderr without tearing through the progress bars.
+/// Pass it to `tracing_subscriber::fmt::layer().with_writer(...)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProgressWriter;
+
+impl Write for ProgressWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        PROGRESS.suspend(|| io::stderr().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for ProgressWriter {
+    type Writer = ProgressWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
@@ -0,0 +1,9 @@
+use getset::Getters;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Getters, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct PublishResponse {
+    pub version_number: u64,
+}
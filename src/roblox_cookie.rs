@@ -1,8 +1,16 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use base64::prelude::*;
 use regex::Regex;
+use secrecy::{ExposeSecret, SecretString, zeroize::Zeroizing};
 use serde::Deserialize;
-use std::{env, fs, path::PathBuf};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::endpoints::RobloxEndpoints;
 
 #[cfg(windows)]
 mod windows_crypto {
@@ -56,6 +64,7 @@ struct CookiesFile {
     cookies_data: String,
 }
 
+#[cfg_attr(not(windows), allow(dead_code))]
 fn clean_value(s: &str) -> String {
     s.trim()
         .trim_end_matches(';')
@@ -63,6 +72,7 @@ fn clean_value(s: &str) -> String {
         .to_string()
 }
 
+#[cfg_attr(not(windows), allow(dead_code))]
 fn extract_roblosecurity(text: &str) -> Option<String> {
     let re = Regex::new(r"(?i)\.ROBLOSECURITY\s+([^;\s#]+)").unwrap();
     for cap in re.captures_iter(text) {
@@ -76,10 +86,25 @@ fn extract_roblosecurity(text: &str) -> Option<String> {
     None
 }
 
+// An env var holding a directory, if it's set to anything
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
 // Gets your .ROBLOSECURITY
-pub fn get_roblosecurity() -> Result<String> {
-    let user_profile = env::var("USERPROFILE")?;
-    let mut cookies_path = PathBuf::from(user_profile);
+pub fn get_roblosecurity() -> Result<SecretString> {
+    // Service accounts can run without USERPROFILE or TEMP set, the OS still
+    // knows where both are
+    let user_profile = env_dir("USERPROFILE")
+        .or_else(dirs::home_dir)
+        .ok_or_else(|| {
+            anyhow!(
+                "Couldn't find the user profile directory to look for Roblox's cookies in: USERPROFILE isn't set and there's no home directory"
+            )
+        })?;
+    let mut cookies_path = user_profile;
     cookies_path.push("AppData");
     cookies_path.push("Local");
     cookies_path.push("Roblox");
@@ -93,51 +118,136 @@ pub fn get_roblosecurity() -> Result<String> {
         )));
     }
 
-    let temp_dir = env::var("TEMP")?;
-    let mut destination_path = PathBuf::from(temp_dir);
-    destination_path.push("RobloxCookies.dat");
+    let temp_dir = env_dir("TEMP").unwrap_or_else(env::temp_dir);
+    let temp_copy = TempCopy::create(&cookies_path, &temp_dir).with_context(|| {
+        format!(
+            "Couldn't copy the cookies file into the temp directory {}",
+            temp_dir.display()
+        )
+    })?;
 
-    let final_destination_path = destination_path.clone();
+    let file_content = fs::read_to_string(&temp_copy.path)?;
+    let parsed_file: CookiesFile = serde_json::from_str(&file_content)?;
 
-    let result = (|| {
-        fs::copy(&cookies_path, &final_destination_path)?;
-        let file_content = fs::read_to_string(&final_destination_path)?;
-        let parsed_file: CookiesFile = serde_json::from_str(&file_content)?;
-
-        let encoded_cookies = parsed_file.cookies_data;
-        if encoded_cookies.is_empty() {
-            return Err(anyhow!("RobloxCookies.dat was found but is empty"));
+    let encoded_cookies = parsed_file.cookies_data;
+    if encoded_cookies.is_empty() {
+        return Err(anyhow!("RobloxCookies.dat was found but is empty"));
+    }
+    let decoded_cookies = BASE64_STANDARD.decode(encoded_cookies)?;
+
+    #[cfg(windows)]
+    {
+        let decrypted_bytes = Zeroizing::new(
+            windows_crypto::dpapi_decrypt(&decoded_cookies)
+                .map_err(|e| anyhow!(format!("Error decrypting with DPAPI: {}", e)))?,
+        );
+
+        let decrypted_string =
+            Zeroizing::new(String::from_utf8_lossy(&decrypted_bytes).into_owned());
+        let roblosecurity = extract_roblosecurity(&decrypted_string);
+        if let Some(roblosecurity) = roblosecurity {
+            return Ok(roblosecurity.into());
         }
-        let decoded_cookies = BASE64_STANDARD.decode(encoded_cookies)?;
+    }
 
-        #[cfg(windows)]
-        {
-            let decrypted_bytes = windows_crypto::dpapi_decrypt(&decoded_cookies)
-                .map_err(|e| anyhow!(format!("Error decrypting with DPAPI: {}", e)))?;
+    #[cfg(not(windows))]
+    {
+        let _ = decoded_cookies;
+        tracing::warn!("DPAPI decryption is only available on Windows.");
+    }
 
-            let decrypted_string = String::from_utf8_lossy(&decrypted_bytes);
-            let roblosecurity = extract_roblosecurity(&decrypted_string);
-            if let Some(roblosecurity) = roblosecurity {
-                return Ok(roblosecurity);
-            }
-        }
+    Err(anyhow!(format!(
+        "Failed to parse cookies at: {:?}",
+        cookies_path
+    )))
+}
 
-        #[cfg(not(windows))]
-        {
-            println!("DPAPI decryption is only available on Windows.");
-        }
+// Private copy of the cookies file, deleted again however get_roblosecurity returns
+struct TempCopy {
+    path: PathBuf,
+}
 
-        return Err(anyhow!(format!(
-            "Failed to parse cookies at: {:?}",
-            cookies_path
-        )));
-    })();
+impl TempCopy {
+    fn create(source: &Path, temp_dir: &Path) -> Result<TempCopy> {
+        // Unique per process and call, so concurrent runs never share the file
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+        let path = temp_dir.join(format!(
+            "RobloxCookies-{}-{}.dat",
+            std::process::id(),
+            nanos
+        ));
+
+        // create_new refuses to follow or reuse an existing file, and on unix the
+        // copy is only readable by us (fs::copy would keep the source's mode)
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut destination = options.open(&path)?;
+        let temp_copy = TempCopy { path };
+
+        io::copy(&mut fs::File::open(source)?, &mut destination)?;
+        Ok(temp_copy)
+    }
+}
 
-    if final_destination_path.exists() {
-        if let Err(e) = fs::remove_file(&final_destination_path) {
-            eprintln!("Failed to delete temporary file: {}", e);
+impl Drop for TempCopy {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::warn!("Failed to delete temporary file: {}", e);
         }
     }
+}
+
+/// Reads a cookie saved by [`save_cached_cookie`]. A missing or empty file is
+/// the same as no cached cookie.
+pub fn load_cached_cookie(path: &Path) -> Result<Option<SecretString>> {
+    if !path.exists() {
+        return Ok(None);
+    }
 
-    result
+    let contents = Zeroizing::new(fs::read_to_string(path)?);
+    let cookie = contents.trim();
+    if cookie.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(cookie.to_string().into()))
+}
+
+/// Saves the cookie so later runs can skip extracting it. Like the temp copy,
+/// the file is only readable by us on unix.
+pub fn save_cached_cookie(path: &Path, cookie: &SecretString) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(cookie.expose_secret().as_bytes())?;
+    Ok(())
+}
+
+/// Checks the cookie still belongs to a logged in session
+pub async fn validate_cookie(
+    client: &reqwest::Client,
+    endpoints: &RobloxEndpoints,
+    cookie: &SecretString,
+) -> Result<bool> {
+    let header = Zeroizing::new(format!(".ROBLOSECURITY={}", cookie.expose_secret()));
+    let response = client
+        .get(format!(
+            "{}/v1/users/authenticated",
+            endpoints.users.trim_end_matches('/')
+        ))
+        .header(reqwest::header::COOKIE, header.as_str())
+        .send()
+        .await?;
+
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Ok(false),
+        status => Err(anyhow!(
+            "unexpected HTTP {} while validating .ROBLOSECURITY",
+            status
+        )),
+    }
 }
@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use rbx_dom_weak::{Instance, WeakDom, ustr};
+use rbx_types::{Ref, Variant};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+use tracing::warn;
+
+// Rojo turns these into directories on its own, anything else becomes a model file
+fn is_container(dom: &WeakDom, instance: &Instance) -> bool {
+    instance.class == "Folder" || instance.parent() == dom.root_ref()
+}
+
+// The file extension Rojo reads back as this script class
+fn script_extension(class: &str) -> Option<&'static str> {
+    match class {
+        "Script" => Some("server.lua"),
+        "LocalScript" => Some("client.lua"),
+        "ModuleScript" => Some("lua"),
+        _ => None,
+    }
+}
+
+fn script_source(instance: &Instance) -> &[u8] {
+    match instance.properties.get(&ustr("Source")) {
+        Some(Variant::String(source)) => source.as_bytes(),
+        Some(Variant::BinaryString(source)) => source.as_ref(),
+        _ => &[],
+    }
+}
+
+// Instance names can hold anything, file names can't
+fn file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match sanitized.trim() {
+        "" | "." | ".." => "_".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+struct Exporter<'a> {
+    dom: &'a WeakDom,
+    renamed: usize,
+}
+
+impl Exporter<'_> {
+    // Writes the children of `parent` into `dir`. Rojo can't tell siblings with
+    // the same name apart, so later ones get a numbered file name and will come
+    // back under that name
+    fn export_children(&mut self, parent: Ref, dir: &Path) -> Result<()> {
+        let mut taken: HashSet<String> = HashSet::new();
+        for &child in self
+            .dom
+            .get_by_ref(parent)
+            .map_or(&[][..], |p| p.children())
+        {
+            let Some(instance) = self.dom.get_by_ref(child) else {
+                continue;
+            };
+            let base = file_name(&instance.name);
+            let mut name = base.clone();
+            let mut n = 1;
+            while !taken.insert(name.to_lowercase()) {
+                n += 1;
+                name = format!("{} ({})", base, n);
+            }
+            if n > 1 {
+                self.renamed += 1;
+            }
+            self.export_instance(instance, &name, dir)?;
+        }
+        Ok(())
+    }
+
+    fn export_instance(&mut self, instance: &Instance, name: &str, dir: &Path) -> Result<()> {
+        if let Some(extension) = script_extension(&instance.class) {
+            let source = script_source(instance);
+            if instance.children().is_empty() {
+                let path = dir.join(format!("{}.{}", name, extension));
+                return fs::write(&path, source)
+                    .with_context(|| format!("Failed to write {}", path.display()));
+            }
+
+            // A script with children becomes a directory with the script as its init file
+            let script_dir = dir.join(name);
+            fs::create_dir_all(&script_dir)
+                .with_context(|| format!("Failed to create {}", script_dir.display()))?;
+            let path = script_dir.join(format!("init.{}", extension));
+            fs::write(&path, source)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            return self.export_children(instance.referent(), &script_dir);
+        }
+
+        if is_container(self.dom, instance) {
+            let child_dir = dir.join(name);
+            fs::create_dir_all(&child_dir)
+                .with_context(|| format!("Failed to create {}", child_dir.display()))?;
+            return self.export_children(instance.referent(), &child_dir);
+        }
+
+        // Everything else goes whole into a model file, scripts inside it included
+        let path = dir.join(format!("{}.rbxmx", name));
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        rbx_xml::to_writer_default(BufWriter::new(file), self.dom, &[instance.referent()])
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Writes a place's instance tree into `dir` the way Rojo lays it out: each
+/// service and Folder is a directory, scripts are `.server.lua`, `.client.lua`
+/// or `.lua` files, and every other instance is an `.rbxmx` model with its
+/// descendants. Whatever `dir` held before is replaced. There's no
+/// `default.project.json`, add one to map the service directories.
+///
+/// Blocks while writing, so run it on the blocking pool.
+pub fn export_place(dom: &WeakDom, dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to clear {}", dir.display()))?;
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut exporter = Exporter { dom, renamed: 0 };
+    exporter.export_children(dom.root_ref(), dir)?;
+    if exporter.renamed > 0 {
+        warn!(
+            "{} instance(s) in {} share a name with a sibling and were exported under a numbered name",
+            exporter.renamed,
+            dir.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::cancel::write_atomically;
+
+/// Where a place is saved in the output directory: `{id}.rbxl`, or
+/// `{id}.rbxl.gz` when compressed
+pub fn saved_place_path(output_dir: &Path, place_id: u64, compressed: bool) -> PathBuf {
+    if compressed {
+        output_dir.join(format!("{}.rbxl.gz", place_id))
+    } else {
+        output_dir.join(format!("{}.rbxl", place_id))
+    }
+}
+
+/// The place id of a file saved by [`write_saved_place`], compressed or not
+pub fn saved_place_id(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name
+        .strip_suffix(".rbxl.gz")
+        .or_else(|| name.strip_suffix(".rbxl"))?;
+    stem.parse().ok()
+}
+
+/// Reads back a place saved by an earlier run, decompressing it if needed.
+/// Both kinds are looked for, the one `compressed` asks for first, so switching
+/// `--compress` on or off still finds what was saved before. `None` when
+/// neither exists. Returns where the place was found along with it
+pub async fn read_saved_place(
+    output_dir: &Path,
+    place_id: u64,
+    compressed: bool,
+) -> io::Result<Option<(PathBuf, Vec<u8>)>> {
+    for compressed in [compressed, !compressed] {
+        let path = saved_place_path(output_dir, place_id, compressed);
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if !compressed {
+            return Ok(Some((path, bytes)));
+        }
+
+        let decompressed = tokio::task::spawn_blocking(move || {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+            Ok::<_, io::Error>(decompressed)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        return Ok(Some((path, decompressed)));
+    }
+    Ok(None)
+}
+
+/// Saves a place to `path`, gzipping it first when `compressed`. Returns how
+/// many bytes ended up on disk
+pub async fn write_saved_place(path: &Path, buffer: &[u8], compressed: bool) -> io::Result<usize> {
+    if !compressed {
+        write_atomically(path, buffer).await?;
+        return Ok(buffer.len());
+    }
+
+    let buffer = buffer.to_vec();
+    let gzipped = tokio::task::spawn_blocking(move || {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&buffer)?;
+        encoder.finish()
+    })
+    .await
+    .map_err(io::Error::other)??;
+    write_atomically(path, &gzipped).await?;
+    Ok(gzipped.len())
+}
@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Token bucket shared by every download, so that together they stay under a
+/// byte rate. It holds at most a second's worth of bytes, which is as much as
+/// it lets through in one burst.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Throttle {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` out of the bucket, waiting until it has refilled enough.
+    /// The bucket stays locked while waiting, so downloads take turns rather
+    /// than all waking up at once.
+    pub async fn consume(&self, bytes: usize) {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.bytes_per_sec;
+        bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec);
+        bucket.refilled = now;
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens < 0.0 {
+            let wait = Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec);
+            tokio::time::sleep(wait).await;
+            bucket.tokens = 0.0;
+            bucket.refilled = Instant::now();
+        }
+    }
+}
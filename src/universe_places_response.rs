@@ -10,7 +10,6 @@ pub struct UniversePlacesResponse {
 
     #[serde(rename = "nextPageCursor")]
     #[get = "pub"]
-    #[allow(dead_code)]
     next_page_cursor: Option<String>,
 
     #[get = "pub"]
@@ -33,4 +32,27 @@ pub struct Place {
     #[get = "pub"]
     #[allow(dead_code)]
     description: String,
+
+    /// When the place was last updated, as an RFC 3339 timestamp
+    #[serde(default)]
+    #[get = "pub"]
+    updated: Option<String>,
+}
+
+impl Place {
+    /// A place known only by its id, like one read from disk with --offline
+    pub(crate) fn from_id(id: u64) -> Self {
+        Place::named(id, id.to_string())
+    }
+
+    /// A place known by its id and name, like one that was already saved
+    pub(crate) fn named(id: u64, name: String) -> Self {
+        Place {
+            id,
+            universe_id: 0,
+            name,
+            description: String::new(),
+            updated: None,
+        }
+    }
 }
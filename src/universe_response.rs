@@ -0,0 +1,17 @@
+use getset::Getters;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Getters, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct UniverseResponse {
+    pub id: u64,
+    pub name: String,
+    /// "User" or "Group"
+    pub creator_type: String,
+    pub creator_target_id: u64,
+    pub creator_name: String,
+    /// The place players join first, usually the starter place
+    #[serde(default)]
+    pub root_place_id: Option<u64>,
+}
@@ -0,0 +1,2864 @@
+use anyhow::{Context, Result, bail};
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{HumanBytes, ProgressBar, ProgressState, ProgressStyle};
+use rbx_dom_weak::{WeakDom, ustr};
+use rbx_types::{Ref, Variant};
+use reqwest::{StatusCode, Url, cookie::Jar};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
+use secrecy::{ExposeSecret, SecretString, zeroize::Zeroizing};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::{
+    asset::{
+        AssetAuth, AssetError, FetchOptions, FetchedAsset, JsonError, JsonResponse, fetch_asset,
+        fetch_asset_bytes, fetch_asset_size, read_local_asset, send_for_json,
+    },
+    cancel::write_atomically,
+    config::{Config, SortOrder},
+    dom_util::{
+        clone_dom, fetched_package_version, full_name, package_link_version, parse_package_id,
+    },
+    endpoints::RobloxEndpoints,
+    failure_log::{Failure, report},
+    lockfile::{Lockfile, package_versions},
+    manifest::{Manifest, ManifestEntry},
+    package_overrides::PackageOverrides,
+    place_diff::{PlaceDiff, diff_doms},
+    place_universe_response::PlaceUniverseResponse,
+    progress::{self, Phase},
+    publish_response::PublishResponse,
+    rojo_export::export_place,
+    saved_file::{read_saved_place, saved_place_id, saved_place_path, write_saved_place},
+    throttle::Throttle,
+    universe_places_response::{Place, UniversePlacesResponse},
+    universe_response::UniverseResponse,
+};
+
+// Package ids sort as numbers, with anything that isn't one after them
+fn package_order(id: &str) -> (u64, &str) {
+    (id.parse().unwrap_or(u64::MAX), id)
+}
+
+// Hosts, subdomains included, that asset downloads can be redirected to and
+// that get the .ROBLOSECURITY along with assetdelivery
+const CDN_COOKIE_DOMAINS: &[&str] = &["rbxcdn.com"];
+
+/// A PackageLink found in a place, and where its package sits in the DOM
+pub struct ToWork {
+    pub package_id_numbers: String,
+    pub package_name: String,
+    pub package_link: Ref,
+    pub package_link_group: Ref,
+    pub package_link_parent: Ref,
+}
+
+/// A downloaded and parsed place along with the PackageLinks found in it
+pub struct PlaceData {
+    pub id: u64,
+    pub name: String,
+    /// SHA-256 of the place file as it was downloaded
+    pub sha256: String,
+    pub dom: WeakDom,
+    pub to_work: Vec<ToWork>,
+    /// Instances of the other classes asked for with --only-scan-classes
+    pub other_matches: Vec<ScannedInstance>,
+}
+
+/// An instance of a class other than PackageLink found while scanning. These
+/// are only reported, never replaced
+pub struct ScannedInstance {
+    pub class: String,
+    pub path: String,
+    /// The package it points at, when it has a `PackageId` property
+    pub package_id: Option<String>,
+}
+
+pub struct PackageStats {
+    pub id: String,
+    pub bytes: usize,
+    pub elapsed: Duration,
+    pub sha256: String,
+    /// The CDN URL had expired and had to be looked up again
+    pub cdn_refreshed: bool,
+    /// Which of the package's source locations it was downloaded from
+    pub location_index: usize,
+    /// How many downloads it took to get a package that parses, with
+    /// `--max-package-retries`. Zero when `--resume` reused the cached copy
+    pub attempts: u32,
+}
+
+/// An updated place, serialized and saved locally, ready to publish
+pub struct SavedPlace {
+    pub id: u64,
+    pub name: String,
+    /// Where the place was saved, or resumed from
+    pub path: PathBuf,
+    pub buffer: Vec<u8>,
+    pub replacements: u32,
+    pub replaced_packages: BTreeSet<String>,
+    pub resumed: bool,
+    /// How many instances the place had before and after its packages were
+    /// replaced. Both zero for resumed places
+    pub instances_before: usize,
+    pub instances_after: usize,
+    /// Recorded in the manifest once the place is published
+    pub manifest_entry: Option<ManifestEntry>,
+}
+
+/// What to do with one place when publishing asks about each of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishDecision {
+    Publish,
+    /// Leave this place unpublished and carry on with the next one
+    Skip,
+    /// Leave this place and every one after it unpublished
+    Abort,
+}
+
+/// Why a package can't be fetched at all, as opposed to a transient failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrokenPackage {
+    /// The asset doesn't exist any more
+    Deleted,
+    /// The asset exists but isn't accessible with the credentials used
+    Private,
+}
+
+impl BrokenPackage {
+    fn from_error(error: &AssetError) -> Option<Self> {
+        match error {
+            AssetError::NotFound { .. } => Some(BrokenPackage::Deleted),
+            AssetError::Unauthorized { .. } => Some(BrokenPackage::Private),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            BrokenPackage::Deleted => "deleted",
+            BrokenPackage::Private => "private or inaccessible",
+        }
+    }
+}
+
+/// The fetched package files by id, their download stats, and the packages that
+/// can't be fetched at all
+pub type FetchedPackages = (
+    HashMap<String, Vec<u8>>,
+    Vec<PackageStats>,
+    BTreeMap<String, BrokenPackage>,
+);
+
+/// A PackageLink pointing at a broken package
+pub struct BrokenReference {
+    pub place_id: u64,
+    pub place_name: String,
+    pub package_id: String,
+    /// Full name of the package instance the PackageLink belongs to
+    pub path: String,
+    pub reason: BrokenPackage,
+}
+
+/// Every PackageLink in the places that points at one of the broken packages
+pub fn broken_references(
+    places_data: &[PlaceData],
+    broken_packages: &BTreeMap<String, BrokenPackage>,
+) -> Vec<BrokenReference> {
+    let mut references = Vec::new();
+    for place in places_data {
+        for work in &place.to_work {
+            if let Some(reason) = broken_packages.get(&work.package_id_numbers) {
+                references.push(BrokenReference {
+                    place_id: place.id,
+                    place_name: place.name.clone(),
+                    package_id: work.package_id_numbers.clone(),
+                    path: full_name(&place.dom, work.package_link_group),
+                    reason: *reason,
+                });
+            }
+        }
+    }
+    references
+}
+
+/// What [`Updater::update_in_batches`] did across all its batches
+pub struct BatchedUpdate {
+    pub saved_places: Vec<SavedPlace>,
+    pub package_stats: Vec<PackageStats>,
+    /// Selected packages that couldn't be fetched, with how many places use each
+    pub unfetched_packages: BTreeMap<String, usize>,
+    pub broken_references: Vec<BrokenReference>,
+}
+
+/// How publishing a single place went: the version it landed at, or why it failed
+pub struct PublishResult {
+    pub id: u64,
+    pub name: String,
+    pub outcome: Result<u64, String>,
+    /// Someone else published the place after it was downloaded, so what was
+    /// saved is based on a stale version
+    pub conflict: bool,
+}
+
+/// Every package id referenced by the given places, once each
+pub fn unique_package_ids(places_data: &[PlaceData]) -> Vec<String> {
+    let mut unique_packages: HashSet<String> = HashSet::new();
+    for p in places_data {
+        for w in &p.to_work {
+            unique_packages.insert(w.package_id_numbers.clone());
+        }
+    }
+    unique_packages.into_iter().collect()
+}
+
+/// Every package id that's referenced by the given places but isn't in
+/// `package_bytes_map`, with how many places reference it
+pub fn missing_packages(
+    places_data: &[PlaceData],
+    package_bytes_map: &HashMap<String, Vec<u8>>,
+) -> BTreeMap<String, usize> {
+    let mut missing: BTreeMap<String, usize> = BTreeMap::new();
+    for p in places_data {
+        let place_packages: BTreeSet<&String> =
+            p.to_work.iter().map(|w| &w.package_id_numbers).collect();
+        for package_id in place_packages {
+            if !package_bytes_map.contains_key(package_id) {
+                *missing.entry(package_id.clone()).or_default() += 1;
+            }
+        }
+    }
+    missing
+}
+
+// Timeout, proxy and TLS settings shared by every client
+fn http_client_builder(config: &Config) -> Result<reqwest::ClientBuilder> {
+    let mut http_builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .user_agent(&config.user_agent);
+    if let Some(proxy) = &config.proxy {
+        let proxy =
+            reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy URL '{}'", proxy))?;
+        http_builder = http_builder.proxy(proxy);
+    }
+    if config.insecure {
+        http_builder = http_builder.danger_accept_invalid_certs(true);
+    }
+    Ok(http_builder)
+}
+
+// Walks every page of the universe's places in order, handing each page over
+// as soon as it arrives. Stops early once `limit` places have been sent.
+async fn fetch_place_pages(
+    client: Arc<ClientWithMiddleware>,
+    options: FetchOptions,
+    universe_id: u64,
+    sort_order: SortOrder,
+    page_size: u32,
+    limit: Option<usize>,
+    pages_tx: mpsc::Sender<Vec<Place>>,
+) -> Result<()> {
+    let mut remaining = limit.unwrap_or(usize::MAX);
+    let mut cursor: Option<String> = None;
+
+    while remaining > 0 {
+        let mut url = format!(
+            "{}/v1/universes/{}/places?sortOrder={}&limit={}",
+            options.endpoints.develop,
+            universe_id,
+            sort_order.as_query(),
+            page_size
+        );
+        if let Some(cursor) = &cursor {
+            url.push_str(&format!("&cursor={}", cursor));
+        }
+
+        // Errors come back as an HTML page or an `errors` object rather than a list
+        let response: UniversePlacesResponse =
+            match send_for_json(client.get(url), options.parse_retries).await {
+                Ok(JsonResponse::Parsed(response)) => response,
+                Ok(JsonResponse::Status(status, body)) => bail!(
+                    "Failed to list the places of universe {}: HTTP {}: {}",
+                    universe_id,
+                    status,
+                    body
+                ),
+                Err(JsonError::Send(e)) => return Err(e.into()),
+                Err(JsonError::Parse(e)) => {
+                    return Err(e.context(format!(
+                        "Failed to parse the places list of universe {}",
+                        universe_id
+                    )));
+                }
+            };
+
+        let page: Vec<Place> = response.data().iter().take(remaining).cloned().collect();
+        remaining -= page.len();
+        // The receiving end only goes away when collecting was cut short
+        if pages_tx.send(page).await.is_err() {
+            break;
+        }
+
+        match response.next_page_cursor() {
+            Some(next) if !next.is_empty() => cursor = Some(next.clone()),
+            _ => break,
+        }
+    }
+
+    Ok(())
+}
+
+// Lists the `<place id>.rbxl` files in `dir` as a single page, by id, for --offline
+async fn list_local_places(
+    dir: PathBuf,
+    limit: Option<usize>,
+    pages_tx: mpsc::Sender<Vec<Place>>,
+) -> Result<()> {
+    let mut place_ids: Vec<u64> = Vec::new();
+    let mut entries = tokio::fs::read_dir(&dir)
+        .await
+        .with_context(|| format!("Failed to read places directory {}", dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "rbxl")
+            && let Some(place_id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+        {
+            place_ids.push(place_id);
+        }
+    }
+    place_ids.sort_unstable();
+
+    let page: Vec<Place> = place_ids
+        .into_iter()
+        .take(limit.unwrap_or(usize::MAX))
+        .map(Place::from_id)
+        .collect();
+    let _ = pages_tx.send(page).await;
+
+    Ok(())
+}
+
+// A place being parsed on the blocking pool, and the SHA-256 of its file
+type ParseTask = (
+    Place,
+    tokio::task::JoinHandle<(String, Result<WeakDom, rbx_binary::DecodeError>)>,
+);
+
+// Every fetched package parsed once, keyed by package id. Ids with the same
+// contents share the one parsed DOM
+type ParsedPackages = Arc<HashMap<String, Arc<WeakDom>>>;
+
+// How many parsed packages --max-places-in-memory keeps between batches
+const PARSED_PACKAGE_CACHE_SIZE: usize = 32;
+
+// The most recently used parsed packages, keyed by the SHA-256 of their
+// contents, so batches that share packages don't parse them again
+struct ParsedPackageCache {
+    packages: HashMap<String, (Arc<WeakDom>, u64)>,
+    capacity: usize,
+    uses: u64,
+}
+
+impl ParsedPackageCache {
+    fn new(capacity: usize) -> Self {
+        ParsedPackageCache {
+            packages: HashMap::new(),
+            capacity,
+            uses: 0,
+        }
+    }
+
+    fn get(&mut self, hash: &str) -> Option<Arc<WeakDom>> {
+        self.uses += 1;
+        let (dom, last_used) = self.packages.get_mut(hash)?;
+        *last_used = self.uses;
+        Some(Arc::clone(dom))
+    }
+
+    // Makes room by dropping the least recently used package
+    fn insert(&mut self, hash: String, dom: Arc<WeakDom>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.uses += 1;
+        if !self.packages.contains_key(&hash)
+            && self.packages.len() >= self.capacity
+            && let Some(oldest) = self
+                .packages
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(hash, _)| hash.clone())
+        {
+            self.packages.remove(&oldest);
+        }
+        self.packages.insert(hash, (dom, self.uses));
+    }
+}
+
+// What replacing the packages in one place did
+struct Replaced {
+    replacements: u32,
+    stripped_links: usize,
+    /// PackageLinks of deleted packages destroyed by --remove-broken. These count
+    /// as replacements too, since the place changed
+    removed_broken: usize,
+    packages: BTreeSet<String>,
+    instances_before: usize,
+    instances_after: usize,
+    /// PackageLinks left alone by --replace-only-if-newer
+    up_to_date: usize,
+}
+
+// Swaps every PackageLink in the place for its fetched package. CPU heavy, so
+// it's run on the blocking pool.
+fn replace_packages(
+    place: &mut PlaceData,
+    parsed_packages: &HashMap<String, Arc<WeakDom>>,
+    broken_packages: &BTreeMap<String, BrokenPackage>,
+    config: &Config,
+    failed_tx: &UnboundedSender<Failure>,
+) -> Replaced {
+    let mut replaced = Replaced {
+        replacements: 0,
+        stripped_links: 0,
+        removed_broken: 0,
+        packages: BTreeSet::new(),
+        instances_before: place.dom.descendants().count(),
+        instances_after: 0,
+        up_to_date: 0,
+    };
+
+    for work in place.to_work.iter() {
+        // A PackageLink nested inside another package goes away with the outer
+        // copy when that one is replaced first
+        if [
+            work.package_link,
+            work.package_link_group,
+            work.package_link_parent,
+        ]
+        .iter()
+        .any(|referent| place.dom.get_by_ref(*referent).is_none())
+        {
+            report(
+                failed_tx,
+                Failure::warning(
+                    format!("place {} ({})", place.name, place.id),
+                    format!(
+                        "PackageLink for package {} is no longer in the place, it was inside a package replaced before it",
+                        work.package_id_numbers
+                    ),
+                ),
+            );
+            continue;
+        }
+
+        if config.remove_broken
+            && broken_packages.get(&work.package_id_numbers) == Some(&BrokenPackage::Deleted)
+        {
+            report(
+                failed_tx,
+                Failure::warning(
+                    format!("place {} ({})", place.name, place.id),
+                    format!(
+                        "removed {} and its PackageLink, package {} was deleted",
+                        full_name(&place.dom, work.package_link_group),
+                        work.package_id_numbers
+                    ),
+                ),
+            );
+            place.dom.destroy(work.package_link_group);
+            replaced.removed_broken += 1;
+            replaced.replacements += 1;
+            continue;
+        }
+
+        // Packages that failed to download or parse were already reported on their own
+        let Some(parsed) = parsed_packages.get(&work.package_id_numbers) else {
+            report(
+                failed_tx,
+                Failure::warning(
+                    format!("place {} ({})", place.name, place.id),
+                    format!(
+                        "no usable package {} for PackageLink at {}, left untouched",
+                        work.package_id_numbers,
+                        full_name(&place.dom, work.package_link_group)
+                    ),
+                ),
+            );
+            continue;
+        };
+
+        // A copy that's already at the fetched version doesn't need replacing.
+        // Without a version on both sides there's nothing to go by, so it is
+        if config.replace_only_if_newer
+            && let Some(current) = place
+                .dom
+                .get_by_ref(work.package_link)
+                .and_then(package_link_version)
+            && let Some(fetched) = fetched_package_version(parsed)
+            && current >= fetched
+        {
+            info!(
+                "{} in place {} ({}) is already up to date at version {} of package {}",
+                full_name(&place.dom, work.package_link_group),
+                place.name,
+                place.id,
+                current,
+                work.package_id_numbers
+            );
+            replaced.up_to_date += 1;
+            continue;
+        }
+
+        // Transferring consumes the DOM, so work on a copy of the parsed package
+        let mut package_dom = clone_dom(parsed);
+
+        // The PackageLink and local edits belong on the first top-level
+        // instance, any others are carried over alongside it
+        let package_roots = package_dom.root().children().to_vec();
+        let Some(&package_root) = package_roots.first() else {
+            report(
+                failed_tx,
+                Failure::error(
+                    format!("place {} ({})", place.name, place.id),
+                    format!(
+                        "package {} has no top-level instances, left untouched",
+                        work.package_id_numbers
+                    ),
+                ),
+            );
+            continue;
+        };
+
+        // Remember local edits to the instanced package before it's replaced
+        let overrides = PackageOverrides::capture(
+            &place.dom,
+            work.package_link_group,
+            config.keep_descendant_overrides,
+        );
+
+        // Bring the new contents in next to the old copy first, the old copy is
+        // only destroyed once everything worth keeping has moved out of it
+        for referent in package_roots {
+            package_dom.transfer(referent, &mut place.dom, work.package_link_parent);
+        }
+
+        // Move the old PackageLink over to the new contents
+        place.dom.transfer_within(work.package_link, package_root);
+        overrides.apply(&mut place.dom, package_root);
+
+        // Move the children to keep from the old copy over too, in place of the
+        // new version's
+        let preserved: Vec<Ref> = place
+            .dom
+            .get_by_ref(work.package_link_group)
+            .map(|group| group.children().to_vec())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|referent| {
+                place
+                    .dom
+                    .get_by_ref(*referent)
+                    .is_some_and(|child| config.preserve_children.contains(&child.name))
+            })
+            .collect();
+        for referent in preserved {
+            let name = place
+                .dom
+                .get_by_ref(referent)
+                .map(|child| child.name.clone());
+            let counterpart = place.dom.get_by_ref(package_root).and_then(|root| {
+                root.children().iter().copied().find(|child| {
+                    place.dom.get_by_ref(*child).map(|child| &child.name) == name.as_ref()
+                })
+            });
+            match counterpart {
+                Some(counterpart) => place.dom.destroy(counterpart),
+                None => report(
+                    failed_tx,
+                    Failure::warning(
+                        format!("place {} ({})", place.name, place.id),
+                        format!(
+                            "package {} has no {} to replace at {}, kept the old one anyway",
+                            work.package_id_numbers,
+                            name.unwrap_or_default(),
+                            full_name(&place.dom, package_root)
+                        ),
+                    ),
+                ),
+            }
+            place.dom.transfer_within(referent, package_root);
+        }
+
+        // Only now that everything worth keeping has moved, destroy the old copy
+        place.dom.destroy(work.package_link_group);
+
+        // Bake the package in, leaving a plain instance tree behind
+        if config.strip_links {
+            let links: Vec<Ref> = place
+                .dom
+                .get_by_ref(package_root)
+                .map(|root| root.children().to_vec())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|referent| {
+                    place
+                        .dom
+                        .get_by_ref(*referent)
+                        .is_some_and(|instance| instance.class == "PackageLink")
+                })
+                .collect();
+            replaced.stripped_links += links.len();
+            for link in links {
+                place.dom.destroy(link);
+            }
+        }
+
+        replaced.replacements += 1;
+        replaced.packages.insert(work.package_id_numbers.clone());
+    }
+
+    replaced.instances_after = place.dom.descendants().count();
+    replaced
+}
+
+// Whether downloaded package bytes parse, for --max-package-retries
+async fn validate_package(bytes: &[u8]) -> Result<(), String> {
+    let bytes = bytes.to_vec();
+    tokio::task::spawn_blocking(move || rbx_binary::from_reader(Cursor::new(bytes)).map(|_| ()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("it doesn't parse: {}", e))
+}
+
+// How many instances of actual content a package has: everything but its
+// PackageLinks and the Model or Folder they sit in
+fn package_content_count(dom: &WeakDom) -> usize {
+    let top_level = dom.root().children();
+    dom.descendants()
+        .filter(|instance| {
+            instance.referent() != dom.root_ref()
+                && instance.class != "PackageLink"
+                && !(top_level.contains(&instance.referent())
+                    && (instance.class == "Model" || instance.class == "Folder"))
+        })
+        .count()
+}
+
+// Parses a serialized place back and checks that no instance got lost on the way
+fn verify_round_trip(dom: &WeakDom, buffer: &[u8]) -> Result<(), String> {
+    let expected = dom.descendants().count();
+    let reparsed = rbx_binary::from_reader(Cursor::new(buffer))
+        .map_err(|e| format!("it doesn't parse back: {}", e))?;
+    let actual = reparsed.descendants().count();
+    if actual != expected {
+        return Err(format!(
+            "it parses back with {} instances instead of {}",
+            actual, expected
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the update pipeline. Anything that goes wrong with a single place or
+/// package is sent to the failure sink and the rest of the run carries on.
+pub struct Updater {
+    client: Arc<ClientWithMiddleware>,
+    config: Config,
+    failed_tx: UnboundedSender<Failure>,
+    spinner_style: ProgressStyle,
+    fetch_options: FetchOptions,
+}
+
+impl Updater {
+    pub fn new(
+        client: ClientWithMiddleware,
+        config: Config,
+        failed_tx: UnboundedSender<Failure>,
+    ) -> Self {
+        let spinner_style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
+            .unwrap()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
+
+        Updater {
+            client: Arc::new(client),
+            failed_tx,
+            spinner_style,
+            fetch_options: FetchOptions {
+                lenient_format: config.lenient_format,
+                auth: AssetAuth::default(),
+                download_timeout: Some(Duration::from_secs(config.download_timeout_secs)),
+                endpoints: config.endpoints.clone().trimmed(),
+                parse_retries: config.max_retries,
+                throttle: config
+                    .max_bytes_per_sec
+                    .map(|bytes_per_sec| Arc::new(Throttle::new(bytes_per_sec))),
+                spill_threshold: (config.spill_threshold_mb > 0)
+                    .then(|| (config.spill_threshold_mb * 1024 * 1024) as usize),
+            },
+            config,
+        }
+    }
+
+    /// Authenticate asset and place downloads this way instead of with the
+    /// client's .ROBLOSECURITY cookie
+    pub fn with_asset_auth(mut self, asset_auth: AssetAuth) -> Self {
+        self.fetch_options.auth = asset_auth;
+        self
+    }
+
+    /// Talk to these Roblox APIs instead of production ones
+    pub fn with_endpoints(mut self, endpoints: RobloxEndpoints) -> Self {
+        self.fetch_options.endpoints = endpoints.trimmed();
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Where an asset's file is with --offline, or None when it's downloaded
+    fn local_asset_path(
+        &self,
+        dir: Option<&String>,
+        asset_id: &str,
+        extension: &str,
+    ) -> Option<PathBuf> {
+        if !self.config.offline {
+            return None;
+        }
+        let dir = dir.map(String::as_str).unwrap_or(".");
+        Some(Path::new(dir).join(format!("{}.{}", asset_id, extension)))
+    }
+
+    // The latest version of a place, from --places-dir when offline
+    async fn download_place(&self, place_id: u64) -> Result<Vec<u8>, AssetError> {
+        let place_id = place_id.to_string();
+        match self.local_asset_path(self.config.places_dir.as_ref(), &place_id, "rbxl") {
+            Some(path) => read_local_asset(&path).await,
+            None => fetch_asset_bytes(&self.client, &place_id, None, &self.fetch_options).await,
+        }
+    }
+
+    // A package, from --packages-dir when offline. Local files only have the one
+    // version, so the pinned version is ignored there
+    async fn download_package(
+        &self,
+        package_id: &str,
+        version: Option<u64>,
+    ) -> Result<FetchedAsset, AssetError> {
+        match self.local_asset_path(self.config.packages_dir.as_ref(), package_id, "rbxm") {
+            Some(path) => Ok(FetchedAsset {
+                bytes: read_local_asset(&path).await?,
+                cdn_refreshed: false,
+                location_index: 0,
+            }),
+            None => fetch_asset(&self.client, package_id, version, &self.fetch_options).await,
+        }
+    }
+
+    // Where a downloaded package is kept for `--resume`, per pinned version
+    fn cached_package_path(&self, package_id: &str, version: Option<u64>) -> PathBuf {
+        let dir = Path::new(&self.config.output_dir).join("packages");
+        match version {
+            Some(version) => dir.join(format!("{}-v{}.rbxm", package_id, version)),
+            None => dir.join(format!("{}.rbxm", package_id)),
+        }
+    }
+
+    // A package kept by an earlier run, if it's whole. An interrupted run can
+    // leave one cut short, and reusing it would break every place using the
+    // package, so one that doesn't start like a Roblox file or doesn't parse is
+    // thrown away and downloaded again
+    async fn read_cached_package(&self, package_id: &str, version: Option<u64>) -> Option<Vec<u8>> {
+        if self.config.offline {
+            return None;
+        }
+        let path = self.cached_package_path(package_id, version);
+        let invalid = match read_local_asset(&path).await {
+            Ok(bytes) => match validate_package(&bytes).await {
+                Ok(()) => {
+                    info!(
+                        "Package {} cache hit, reusing {}",
+                        package_id,
+                        path.display()
+                    );
+                    return Some(bytes);
+                }
+                Err(e) => e,
+            },
+            Err(AssetError::LocalRead { source, .. })
+                if source.kind() == std::io::ErrorKind::NotFound =>
+            {
+                info!("Package {} cache miss, downloading it", package_id);
+                return None;
+            }
+            Err(e) => e.to_string(),
+        };
+
+        warn!(
+            "Package {} cache entry {} is unusable, downloading it again: {}",
+            package_id,
+            path.display(),
+            invalid
+        );
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+        None
+    }
+
+    // Keeps a downloaded package for a later `--resume`. Not being able to is
+    // only a warning, the run itself doesn't need the copy
+    async fn cache_package(&self, package_id: &str, version: Option<u64>, bytes: &[u8]) {
+        if self.config.offline {
+            return;
+        }
+        let path = self.cached_package_path(package_id, version);
+        let written = async {
+            tokio::fs::create_dir_all(Path::new(&self.config.output_dir).join("packages")).await?;
+            write_atomically(&path, bytes).await
+        };
+        if let Err(e) = written.await {
+            warn!(
+                "Failed to cache package {} at {}: {}",
+                package_id,
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Downloads the latest version of one package, or reads it from
+    /// `--packages-dir` when offline, and parses it
+    pub async fn fetch_package_dom(&self, package_id: &str) -> Result<WeakDom> {
+        let fetched = self
+            .download_package(package_id, None)
+            .await
+            .with_context(|| format!("Failed to fetch package {}", package_id))?;
+        let dom = tokio::task::spawn_blocking(move || {
+            rbx_binary::from_reader(Cursor::new(fetched.bytes))
+        })
+        .await?
+        .with_context(|| format!("Failed to parse package {}", package_id))?;
+        Ok(dom)
+    }
+
+    /// Whether `--only-scan-classes` asks for instances of this class
+    pub fn is_class_scanned(&self, class: &str) -> bool {
+        self.config
+            .only_scan_classes
+            .iter()
+            .any(|scanned| scanned == class)
+    }
+
+    /// Whether `--packages` lets this package be fetched and replaced
+    pub fn is_package_selected(&self, package_id: &str) -> bool {
+        self.config.packages.is_empty() || self.config.packages.iter().any(|id| id == package_id)
+    }
+
+    /// Builds an unauthenticated HTTP client without retries, using the proxy and
+    /// TLS settings taken from `config`.
+    pub fn build_plain_client(config: &Config) -> Result<reqwest::Client> {
+        Ok(http_client_builder(config)?.build()?)
+    }
+
+    /// Builds an HTTP client authenticated with the given .ROBLOSECURITY, if any,
+    /// with retries, proxy and TLS settings taken from `config`.
+    pub fn build_client(
+        config: &Config,
+        rbxl_cookie: Option<&SecretString>,
+    ) -> Result<ClientWithMiddleware> {
+        // Set up a client with exponential backoff
+        let retry_policy = ExponentialBackoff::builder()
+            .jitter(config.retry_jitter.into())
+            .build_with_max_retries(config.max_retries);
+        let jar = Jar::default();
+        if let Some(rbxl_cookie) = rbxl_cookie {
+            let cookie = Zeroizing::new(format!(".ROBLOSECURITY={}", rbxl_cookie.expose_secret()));
+            // Every Roblox host this client talks to gets the cookie. Publishing and
+            // Open Cloud downloads send the API key instead, but listing the places
+            // of a private universe or looking up a private place's universe needs
+            // the cookie, and assetdelivery always does
+            let endpoints = &config.endpoints;
+            for (name, url) in [
+                ("assetdelivery", &endpoints.assetdelivery),
+                ("develop", &endpoints.develop),
+                ("apis", &endpoints.apis),
+                ("users", &endpoints.users),
+            ] {
+                jar.add_cookie_str(
+                    &cookie,
+                    &url.parse::<Url>()
+                        .with_context(|| format!("Invalid {} URL", name))?,
+                );
+            }
+            // Source locations can redirect to a CDN host that wants the cookie too
+            for domain in CDN_COOKIE_DOMAINS {
+                jar.add_cookie_str(
+                    &Zeroizing::new(format!("{}; Domain={}; Secure", cookie.as_str(), domain)),
+                    &format!("https://{}", domain).parse::<Url>()?,
+                );
+            }
+        }
+        let cookies = Arc::new(jar);
+        let http_builder = http_client_builder(config)?.cookie_provider(Arc::clone(&cookies));
+        if config.insecure {
+            warn!("TLS certificate verification is disabled (--insecure)");
+        }
+        let mut client_builder = ClientBuilder::new(http_builder.build()?);
+        if config.max_retries == 0 {
+            info!("Retries: disabled");
+        } else {
+            info!(
+                "Retries: up to {} (jitter: {:?})",
+                config.max_retries, config.retry_jitter
+            );
+            client_builder =
+                client_builder.with(RetryTransientMiddleware::new_with_policy(retry_policy));
+        }
+
+        Ok(client_builder.build())
+    }
+
+    /// Looks up the universe's name and who owns it
+    pub async fn fetch_universe(&self, universe_id: u64) -> Result<UniverseResponse> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/v1/universes/{}",
+                self.fetch_options.endpoints.develop, universe_id
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<UniverseResponse>()
+            .await
+            .with_context(|| format!("Failed to look up universe {}", universe_id))?;
+
+        Ok(response)
+    }
+
+    // Looks up which universe a place belongs to
+    pub async fn resolve_universe_id(&self, place_id: u64) -> Result<u64> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/universes/v1/places/{}/universe",
+                self.fetch_options.endpoints.apis, place_id
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<PlaceUniverseResponse>()
+            .await
+            .with_context(|| format!("Failed to resolve the universe of place {}", place_id))?;
+
+        Ok(*response.universe_id())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn collect_places_and_package_ids(&self, universe_id: u64) -> Result<Vec<PlaceData>> {
+        let mut places_data: Vec<PlaceData> = Vec::new();
+        self.collect_places_in_batches(universe_id, usize::MAX, async |batch| {
+            places_data.extend(batch);
+            Ok(())
+        })
+        .await?;
+        Ok(places_data)
+    }
+
+    /// Like [`Updater::collect_places_and_package_ids`], but hands the places
+    /// over `batch_size` at a time as soon as they're scanned, waiting for
+    /// `on_batch` before downloading any more. At most `batch_size` places are
+    /// held here at once.
+    pub async fn collect_places_in_batches(
+        &self,
+        universe_id: u64,
+        batch_size: usize,
+        mut on_batch: impl AsyncFnMut(Vec<PlaceData>) -> Result<()>,
+    ) -> Result<()> {
+        progress::set_phase(Phase::CollectPlaces);
+        if let Some(limit) = self.config.limit_places {
+            warn!(
+                "--limit-places {} is in effect, this is not a full run",
+                limit
+            );
+            report(
+                &self.failed_tx,
+                Failure::note(
+                    "run",
+                    format!(
+                        "only the first {} place(s) were processed because of --limit-places",
+                        limit
+                    ),
+                ),
+            );
+        }
+
+        // The root place is often a template, so it's looked up before anything is
+        // downloaded. Better to stop than to touch it by mistake
+        let root_place_id = if !self.config.skip_root_place {
+            None
+        } else if self.config.offline {
+            warn!("--skip-root-place has no effect offline, there's no universe to ask");
+            None
+        } else {
+            let universe = self.fetch_universe(universe_id).await?;
+            let Some(root_place_id) = *universe.root_place_id() else {
+                bail!(
+                    "Universe {} has no root place to skip for --skip-root-place",
+                    universe_id
+                );
+            };
+            info!(
+                "Place {} is the root place of universe {}, skipping it",
+                root_place_id, universe_id
+            );
+            Some(root_place_id)
+        };
+
+        // The places list is paged through in the background while the places on
+        // pages that already arrived get downloaded. Places are still downloaded,
+        // and returned, in the order the list gives them.
+        let (pages_tx, mut pages_rx) = mpsc::channel(self.config.pages_concurrency.max(1));
+        let pages_task = if self.config.offline {
+            let places_dir = PathBuf::from(self.config.places_dir.as_deref().unwrap_or("."));
+            tokio::spawn(list_local_places(
+                places_dir,
+                self.config.limit_places,
+                pages_tx,
+            ))
+        } else {
+            tokio::spawn(fetch_place_pages(
+                Arc::clone(&self.client),
+                self.fetch_options.clone(),
+                universe_id,
+                self.config.sort_order,
+                self.config.page_size,
+                self.config.limit_places,
+                pages_tx,
+            ))
+        };
+
+        // Download each place once, parse and record PackageLink occurrences.
+        // Parsing runs on the blocking pool so the next download can start meanwhile.
+        let places_pb = progress::add(ProgressBar::new(0));
+        places_pb.set_style(self.spinner_style.clone());
+        places_pb.set_prefix("[places]");
+        places_pb.set_message("Fetching places list");
+        let parse_pb = progress::add(ProgressBar::new(0));
+        parse_pb.set_style(self.spinner_style.clone());
+        parse_pb.set_prefix("[parse]");
+
+        let mut parse_tasks: Vec<ParseTask> = Vec::new();
+        let since_cutoff = self
+            .config
+            .since
+            .map(|since| SystemTime::now().checked_sub(since).unwrap_or(UNIX_EPOCH));
+        let mut not_recent = 0usize;
+        let mut listed = 0usize;
+
+        info!("Found places:");
+        while let Some(page) = pages_rx.recv().await {
+            listed += page.len();
+            places_pb.inc_length(page.len() as u64);
+            for place in page.iter() {
+                info!("> {} (id: {})", place.name(), place.id());
+            }
+
+            for place in page {
+                if root_place_id == Some(*place.id()) {
+                    report(
+                        &self.failed_tx,
+                        Failure::skipped(
+                            format!("place {} ({})", place.name(), place.id()),
+                            "it's the universe's root place, skipped by --skip-root-place",
+                        ),
+                    );
+                    places_pb.inc(1);
+                    continue;
+                }
+
+                if let Some(cutoff) = since_cutoff {
+                    match place
+                        .updated()
+                        .as_deref()
+                        .map(humantime::parse_rfc3339_weak)
+                    {
+                        Some(Ok(updated)) if updated < cutoff => {
+                            not_recent += 1;
+                            places_pb.inc(1);
+                            continue;
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => {
+                            report(
+                                &self.failed_tx,
+                                Failure::warning(
+                                    format!("place {} ({})", place.name(), place.id()),
+                                    format!(
+                                        "couldn't read when it was updated, processing it anyway: {}",
+                                        e
+                                    ),
+                                ),
+                            );
+                        }
+                        None => {
+                            report(
+                                &self.failed_tx,
+                                Failure::warning(
+                                    format!("place {} ({})", place.name(), place.id()),
+                                    "no update time, processing it anyway despite --since",
+                                ),
+                            );
+                        }
+                    }
+                }
+
+                places_pb.set_message(format!(
+                    "Downloading place {} ({})",
+                    place.name(),
+                    place.id()
+                ));
+                let place_bytes = match self.download_place(*place.id()).await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        report(
+                            &self.failed_tx,
+                            Failure::error(
+                                format!("place {} ({})", place.name(), place.id()),
+                                format!("failed to download: {}", e),
+                            ),
+                        );
+                        places_pb.inc(1);
+                        continue;
+                    }
+                };
+
+                parse_pb.inc_length(1);
+                let task_parse_pb = parse_pb.clone();
+                let place_id = *place.id();
+                let parse_task = tokio::task::spawn_blocking(move || {
+                    task_parse_pb.set_message(format!("Parsing place DOM {}", place_id));
+                    let sha256 = hex::encode(Sha256::digest(&place_bytes));
+                    let dom = rbx_binary::from_reader(Cursor::new(place_bytes));
+                    task_parse_pb.inc(1);
+                    (sha256, dom)
+                });
+                parse_tasks.push((place, parse_task));
+
+                places_pb.inc(1);
+
+                if parse_tasks.len() >= batch_size {
+                    let batch = self.finish_scanning(std::mem::take(&mut parse_tasks)).await;
+                    on_batch(batch).await?;
+                    progress::set_phase(Phase::CollectPlaces);
+                }
+            }
+        }
+
+        // A failure to list the places is fatal, same as before any were downloaded
+        pages_task.await??;
+
+        places_pb.finish_with_message("Finished downloading places");
+        if listed == 0 {
+            if self.config.offline {
+                warn!(
+                    "No .rbxl places found in {}",
+                    self.config.places_dir.as_deref().unwrap_or(".")
+                );
+            } else {
+                warn!(
+                    "Universe {} has no accessible places (check cookie/permissions)",
+                    universe_id
+                );
+            }
+        }
+        if not_recent > 0 {
+            info!(
+                "Skipped {} place(s) not updated within --since {}",
+                not_recent,
+                humantime::format_duration(self.config.since.unwrap_or_default())
+            );
+        }
+
+        if !parse_tasks.is_empty() {
+            on_batch(self.finish_scanning(parse_tasks).await).await?;
+        }
+
+        parse_pb.finish_with_message("Finished scanning places");
+
+        Ok(())
+    }
+
+    // Waits for the places to be parsed and scans each one for PackageLinks
+    async fn finish_scanning(&self, parse_tasks: Vec<ParseTask>) -> Vec<PlaceData> {
+        let mut places_data: Vec<PlaceData> = Vec::new();
+        for (place, parse_task) in parse_tasks {
+            let (sha256, dom) = match parse_task.await {
+                Ok((sha256, Ok(d))) => (sha256, d),
+                Ok((_, Err(e))) => {
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("place {} ({})", place.name(), place.id()),
+                            format!("failed to parse RBX binary: {}", e),
+                        ),
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("place {} ({})", place.name(), place.id()),
+                            format!("parsing task failed: {}", e),
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            // Scan for PackageLink instances, and any other classes asked for
+            let mut to_work: Vec<ToWork> = Vec::new();
+            let mut other_matches: Vec<ScannedInstance> = Vec::new();
+            for instance in dom.descendants() {
+                if !self.is_class_scanned(&instance.class) {
+                    continue;
+                }
+
+                // Only PackageLinks can be replaced, anything else is just reported
+                if instance.class != "PackageLink" {
+                    let package_id = match instance.properties.get(&ustr("PackageId")) {
+                        Some(Variant::ContentId(id)) => parse_package_id(id.as_str()),
+                        _ => None,
+                    };
+                    other_matches.push(ScannedInstance {
+                        class: instance.class.to_string(),
+                        path: full_name(&dom, instance.referent()),
+                        package_id,
+                    });
+                    continue;
+                }
+
+                // AutoUpdate = false means the developer pinned this package on purpose
+                if !self.config.force_all
+                    && let Some(Variant::Bool(false)) = instance.properties.get(&ustr("AutoUpdate"))
+                {
+                    report(
+                        &self.failed_tx,
+                        Failure::skipped(
+                            format!("place {} ({})", place.name(), place.id()),
+                            format!(
+                                "PackageLink {} has AutoUpdate disabled (use --force-all to replace it)",
+                                instance.referent()
+                            ),
+                        ),
+                    );
+                    continue;
+                }
+
+                // Get PackageId
+                let package_id = match instance.properties.get(&ustr("PackageId")) {
+                    Some(Variant::ContentId(id)) => id.clone(),
+                    _ => {
+                        report(
+                            &self.failed_tx,
+                            Failure::warning(
+                                format!("place {} ({})", place.name(), place.id()),
+                                "PackageLink without valid PackageId",
+                            ),
+                        );
+                        continue;
+                    }
+                };
+
+                let package_id_numbers = match parse_package_id(package_id.as_str()) {
+                    Some(s) => s,
+                    None => {
+                        report(
+                            &self.failed_tx,
+                            Failure::warning(
+                                format!("place {} ({})", place.name(), place.id()),
+                                format!(
+                                    "PackageId had unexpected format '{}'",
+                                    package_id.as_str()
+                                ),
+                            ),
+                        );
+                        continue;
+                    }
+                };
+                if !package_id.as_str().starts_with("rbxassetid://") {
+                    report(
+                        &self.failed_tx,
+                        Failure::note(
+                            format!("place {} ({})", place.name(), place.id()),
+                            format!(
+                                "PackageId '{}' isn't an rbxassetid://, read it as package {}",
+                                package_id.as_str(),
+                                package_id_numbers
+                            ),
+                        ),
+                    );
+                }
+
+                // The package and whatever holds it both have to be there for the
+                // new copy to have somewhere to go
+                let package_link_group = instance.parent();
+                let package_link = instance.referent();
+                let Some(group) = dom
+                    .get_by_ref(package_link_group)
+                    .filter(|group| dom.get_by_ref(group.parent()).is_some())
+                else {
+                    report(
+                        &self.failed_tx,
+                        Failure::warning(
+                            format!("place {} ({})", place.name(), place.id()),
+                            format!(
+                                "PackageLink {} for package {} has no package instance with a parent to replace, left untouched",
+                                package_link, package_id_numbers
+                            ),
+                        ),
+                    );
+                    continue;
+                };
+                let package_name = group.name.clone();
+                let package_link_parent = group.parent();
+
+                // A package pointing back at the place it lives in would have the
+                // place transferred into itself
+                if package_id_numbers == place.id().to_string() {
+                    report(
+                        &self.failed_tx,
+                        Failure::skipped(
+                            format!("place {} ({})", place.name(), place.id()),
+                            format!(
+                                "PackageLink at {} refers to its own place as package {}",
+                                full_name(&dom, package_link_group),
+                                package_id_numbers
+                            ),
+                        ),
+                    );
+                    continue;
+                }
+
+                to_work.push(ToWork {
+                    package_id_numbers,
+                    package_name,
+                    package_link,
+                    package_link_group,
+                    package_link_parent,
+                });
+            }
+
+            if !other_matches.is_empty() {
+                info!(
+                    "Found {} instance(s) of other scanned classes in place {} ({}), not replaced",
+                    other_matches.len(),
+                    place.name(),
+                    place.id()
+                );
+            }
+
+            places_data.push(PlaceData {
+                id: *place.id(),
+                name: place.name().to_string(),
+                sha256,
+                dom,
+                to_work,
+                other_matches,
+            });
+        }
+
+        places_data
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn fetch_package_assets(
+        &self,
+        package_ids: Vec<String>,
+        pinned_versions: &BTreeMap<String, u64>,
+    ) -> FetchedPackages {
+        let package_ids: Vec<String> = package_ids
+            .into_iter()
+            .filter(|id| self.is_package_selected(id))
+            .collect();
+        progress::set_phase(Phase::FetchPackages);
+        // Downloads finish out of order, so throughput comes from a shared byte count
+        let downloaded_bytes = Arc::new(AtomicU64::new(0));
+        let throughput_bytes = Arc::clone(&downloaded_bytes);
+        let packages_style = ProgressStyle::with_template(
+            "{prefix:.bold.dim} {spinner} {pos}/{len} ({eta} left, {throughput}) {wide_msg}",
+        )
+        .unwrap()
+        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+        .with_key(
+            "throughput",
+            move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let seconds = state.elapsed().as_secs_f64().max(0.001);
+                let bytes = throughput_bytes.load(Ordering::Relaxed) as f64;
+                let _ = write!(w, "{}/s", HumanBytes((bytes / seconds) as u64));
+            },
+        );
+        let packages_pb = progress::add(ProgressBar::new(package_ids.len() as u64));
+        packages_pb.set_style(packages_style);
+        packages_pb.set_prefix("[packages]");
+
+        let mut package_results =
+            futures::stream::iter(package_ids.into_iter().map(|package_id_numbers| {
+                let packages_pb = packages_pb.clone();
+                let downloaded_bytes = Arc::clone(&downloaded_bytes);
+                let failed_tx = self.failed_tx.clone();
+                let pinned_version = pinned_versions.get(&package_id_numbers).copied();
+                async move {
+                    let started = Instant::now();
+                    if self.config.resume
+                        && let Some(bytes) = self
+                            .read_cached_package(&package_id_numbers, pinned_version)
+                            .await
+                    {
+                        packages_pb.inc(1);
+                        let package = FetchedAsset {
+                            bytes,
+                            cdn_refreshed: false,
+                            location_index: 0,
+                        };
+                        return Ok((package_id_numbers, package, started.elapsed(), 0));
+                    }
+                    packages_pb.set_message(format!("Downloading package {}", package_id_numbers));
+
+                    // Bad bytes can be a one-off, so with --max-package-retries a
+                    // package that isn't a Roblox file or doesn't parse is fetched
+                    // again. After the last retry it's passed on as it is, and
+                    // parsing reports it like any other broken package
+                    let mut attempts = 0;
+                    let package = loop {
+                        attempts += 1;
+                        let retries_left = attempts <= self.config.max_package_retries;
+                        let invalid = match self
+                            .download_package(&package_id_numbers, pinned_version)
+                            .await
+                        {
+                            Ok(package) if retries_left => {
+                                match validate_package(&package.bytes).await {
+                                    Ok(()) => break Ok(package),
+                                    Err(e) => e,
+                                }
+                            }
+                            Err(e @ AssetError::NotRobloxBinary { .. }) if retries_left => {
+                                e.to_string()
+                            }
+                            result => break result,
+                        };
+                        report(
+                            &failed_tx,
+                            Failure::note(
+                                format!("package {}", package_id_numbers),
+                                format!(
+                                    "attempt {} of {} was unusable, downloading it again: {}",
+                                    attempts,
+                                    self.config.max_package_retries + 1,
+                                    invalid
+                                ),
+                            ),
+                        );
+                        packages_pb.set_message(format!(
+                            "Downloading package {} again (attempt {})",
+                            package_id_numbers,
+                            attempts + 1
+                        ));
+                    };
+
+                    let package = match package {
+                        Ok(b) => b,
+                        Err(e) => {
+                            report(
+                                &failed_tx,
+                                Failure::error(
+                                    format!("package {}", package_id_numbers),
+                                    format!("failed to download: {}", e),
+                                ),
+                            );
+                            packages_pb.inc(1);
+                            return Err((package_id_numbers, e));
+                        }
+                    };
+
+                    let elapsed = started.elapsed();
+                    downloaded_bytes.fetch_add(package.bytes.len() as u64, Ordering::Relaxed);
+                    self.cache_package(&package_id_numbers, pinned_version, &package.bytes)
+                        .await;
+                    packages_pb.inc(1);
+                    Ok((package_id_numbers, package, elapsed, attempts))
+                }
+            }))
+            .buffer_unordered(self.config.download_concurrency.max(1))
+            .collect::<Vec<Result<(String, FetchedAsset, Duration, u32), (String, AssetError)>>>()
+            .await;
+
+        packages_pb.finish_with_message("Finished fetching packages");
+        let total_bytes = downloaded_bytes.load(Ordering::Relaxed);
+        if total_bytes > 0 {
+            let seconds = packages_pb.elapsed().as_secs_f64().max(0.001);
+            info!(
+                "Downloaded {} of packages in {:.1}s, an effective {}/s",
+                HumanBytes(total_bytes),
+                seconds,
+                HumanBytes((total_bytes as f64 / seconds) as u64)
+            );
+        }
+
+        // Downloads finish in any order, so everything after this goes by package id
+        package_results.sort_by(|a, b| {
+            let (Ok((a, ..)) | Err((a, _))) = a;
+            let (Ok((b, ..)) | Err((b, _))) = b;
+            package_order(a).cmp(&package_order(b))
+        });
+
+        // Collect successful package bytes
+        let mut package_bytes_map: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut package_stats: Vec<PackageStats> = Vec::new();
+        let mut broken_packages: BTreeMap<String, BrokenPackage> = BTreeMap::new();
+        for res in package_results.into_iter() {
+            match res {
+                Ok((id, package, elapsed, attempts)) => {
+                    package_stats.push(PackageStats {
+                        id: id.clone(),
+                        bytes: package.bytes.len(),
+                        elapsed,
+                        sha256: hex::encode(Sha256::digest(&package.bytes)),
+                        cdn_refreshed: package.cdn_refreshed,
+                        location_index: package.location_index,
+                        attempts,
+                    });
+                    package_bytes_map.insert(id, package.bytes);
+                }
+                Err((id, e)) => {
+                    if let Some(broken) = BrokenPackage::from_error(&e) {
+                        broken_packages.insert(id.clone(), broken);
+                    }
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("package {}", id),
+                            format!(
+                                "failed to fetch ({}, see earlier messages), its PackageLink(s) are left untouched",
+                                e.kind()
+                            ),
+                        ),
+                    );
+                }
+            }
+        }
+
+        (package_bytes_map, package_stats, broken_packages)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn process_places_and_save(
+        &self,
+        places_data: Vec<PlaceData>,
+        package_bytes_map: HashMap<String, Vec<u8>>,
+        package_hashes: &HashMap<String, String>,
+        broken_packages: &BTreeMap<String, BrokenPackage>,
+    ) -> Result<Vec<SavedPlace>> {
+        self.process_places(
+            places_data,
+            package_bytes_map,
+            package_hashes,
+            broken_packages,
+            None,
+        )
+        .await
+    }
+
+    // Same as process_places_and_save, reusing packages parsed for an earlier
+    // batch when there's a cache
+    async fn process_places(
+        &self,
+        places_data: Vec<PlaceData>,
+        package_bytes_map: HashMap<String, Vec<u8>>,
+        package_hashes: &HashMap<String, String>,
+        broken_packages: &BTreeMap<String, BrokenPackage>,
+        cache: Option<&mut ParsedPackageCache>,
+    ) -> Result<Vec<SavedPlace>> {
+        progress::set_phase(Phase::ProcessPlaces);
+        let save_pb = progress::add(ProgressBar::new(places_data.len() as u64));
+        save_pb.set_style(self.spinner_style.clone());
+        save_pb.set_prefix("[save]");
+
+        let output_dir = Path::new(&self.config.output_dir);
+        tokio::fs::create_dir_all(output_dir).await?;
+        let manifest = Manifest::load(&output_dir.join("manifest.json")).await?;
+
+        let parsed_packages = self
+            .parse_packages(package_bytes_map, package_hashes, cache)
+            .await;
+        let broken_packages = Arc::new(broken_packages.clone());
+
+        // Every place's DOM is independent once the packages are parsed, so the
+        // replacing and serializing runs for several places at once. The packages
+        // are only ever read, and `buffered` keeps the places in their original order.
+        let saved_places: Vec<SavedPlace> =
+            futures::stream::iter(places_data.into_iter().map(|place| {
+                self.process_place(
+                    place,
+                    package_hashes,
+                    &parsed_packages,
+                    &broken_packages,
+                    &manifest,
+                    &save_pb,
+                )
+            }))
+            .buffered(self.config.process_concurrency.max(1))
+            .try_collect::<Vec<Option<SavedPlace>>>()
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        save_pb.finish_with_message("Saved all updated places locally (not published)");
+
+        Ok(saved_places)
+    }
+
+    /// Downloads these places again and redoes their replacements on top of
+    /// what's live now, for places someone else published after they were
+    /// downloaded. Packages are fetched through the lockfile again, so with
+    /// `--locked` they stay at the same versions. Places that fail are reported
+    /// and left out
+    #[tracing::instrument(skip_all)]
+    pub async fn update_places_again(&self, stale: Vec<SavedPlace>) -> Result<Vec<SavedPlace>> {
+        let mut parse_tasks: Vec<ParseTask> = Vec::new();
+        for saved in stale {
+            let place_bytes = match self.download_place(saved.id).await {
+                Ok(b) => b,
+                Err(e) => {
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("place {} ({})", saved.name, saved.id),
+                            format!("failed to download again: {}", e),
+                        ),
+                    );
+                    continue;
+                }
+            };
+            let parse_task = tokio::task::spawn_blocking(move || {
+                let sha256 = hex::encode(Sha256::digest(&place_bytes));
+                (sha256, rbx_binary::from_reader(Cursor::new(place_bytes)))
+            });
+            parse_tasks.push((Place::named(saved.id, saved.name), parse_task));
+        }
+        let places_data = self.finish_scanning(parse_tasks).await;
+
+        let (package_bytes_map, package_stats, broken_packages) = self
+            .fetch_locked_package_assets(unique_package_ids(&places_data))
+            .await?;
+        let package_hashes: HashMap<String, String> = package_stats
+            .into_iter()
+            .map(|stats| (stats.id, stats.sha256))
+            .collect();
+
+        // The stale copies are still in the output directory, and must not be
+        // resumed from
+        let fresh = Updater {
+            client: Arc::clone(&self.client),
+            config: Config {
+                resume: false,
+                ..self.config.clone()
+            },
+            failed_tx: self.failed_tx.clone(),
+            spinner_style: self.spinner_style.clone(),
+            fetch_options: self.fetch_options.clone(),
+        };
+        fresh
+            .process_places_and_save(
+                places_data,
+                package_bytes_map,
+                &package_hashes,
+                &broken_packages,
+            )
+            .await
+    }
+
+    // Parses each fetched package once, up front, so places only ever copy them.
+    // Packages with the same contents are parsed once between them, and ones
+    // already in the cache aren't parsed at all.
+    async fn parse_packages(
+        &self,
+        package_bytes_map: HashMap<String, Vec<u8>>,
+        package_hashes: &HashMap<String, String>,
+        mut cache: Option<&mut ParsedPackageCache>,
+    ) -> ParsedPackages {
+        let mut ids_by_contents: HashMap<String, Vec<String>> = HashMap::new();
+        let mut bytes_by_contents: HashMap<String, Vec<u8>> = HashMap::new();
+        for (package_id, bytes) in package_bytes_map {
+            let key = package_hashes
+                .get(&package_id)
+                .unwrap_or(&package_id)
+                .clone();
+            bytes_by_contents.entry(key.clone()).or_insert(bytes);
+            ids_by_contents.entry(key).or_default().push(package_id);
+        }
+
+        let mut parsed_packages: HashMap<String, Arc<WeakDom>> = HashMap::new();
+        if let Some(cache) = cache.as_deref_mut() {
+            bytes_by_contents.retain(|hash, _| {
+                let Some(dom) = cache.get(hash) else {
+                    return true;
+                };
+                for package_id in ids_by_contents.remove(hash).unwrap_or_default() {
+                    parsed_packages.insert(package_id, Arc::clone(&dom));
+                }
+                false
+            });
+        }
+
+        let parse_pb = progress::add(ProgressBar::new(bytes_by_contents.len() as u64));
+        parse_pb.set_style(self.spinner_style.clone());
+        parse_pb.set_prefix("[packages]");
+        parse_pb.set_message("Parsing packages");
+
+        let parsed = futures::stream::iter(bytes_by_contents.into_iter().map(|(hash, bytes)| {
+            let parse_pb = parse_pb.clone();
+            async move {
+                let parsed = tokio::task::spawn_blocking(move || {
+                    rbx_binary::from_reader(Cursor::new(bytes)).map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|parsed| parsed);
+                parse_pb.inc(1);
+                (hash, parsed)
+            }
+        }))
+        .buffer_unordered(self.config.process_concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+        parse_pb.finish_and_clear();
+
+        for (hash, parsed) in parsed {
+            let package_ids = ids_by_contents.remove(&hash).unwrap_or_default();
+            let usable = match parsed {
+                // Swapping in an empty package would delete what the places have
+                Ok(dom) if package_content_count(&dom) == 0 => {
+                    let message = if self.config.allow_empty_packages {
+                        "has no content besides its PackageLink, replacing it anyway because of --allow-empty-packages"
+                    } else {
+                        "has no content besides its PackageLink, its PackageLink(s) are left untouched so the places using it aren't emptied (pass --allow-empty-packages to replace it anyway)"
+                    };
+                    for package_id in &package_ids {
+                        report(
+                            &self.failed_tx,
+                            Failure::warning(format!("package {}", package_id), message),
+                        );
+                    }
+                    self.config.allow_empty_packages.then_some(dom)
+                }
+                Ok(dom) => Some(dom),
+                Err(e) => {
+                    for package_id in &package_ids {
+                        report(
+                            &self.failed_tx,
+                            Failure::error(
+                                format!("package {}", package_id),
+                                format!(
+                                    "failed to parse, its PackageLink(s) are left untouched: {}",
+                                    e
+                                ),
+                            ),
+                        );
+                    }
+                    None
+                }
+            };
+
+            if let Some(dom) = usable {
+                let dom = Arc::new(dom);
+                if let Some(cache) = cache.as_deref_mut() {
+                    cache.insert(hash, Arc::clone(&dom));
+                }
+                for package_id in package_ids {
+                    parsed_packages.insert(package_id, Arc::clone(&dom));
+                }
+            }
+        }
+
+        Arc::new(parsed_packages)
+    }
+
+    // Replaces the packages in one place and saves it, or returns None when the
+    // place is skipped
+    async fn process_place(
+        &self,
+        mut place: PlaceData,
+        package_hashes: &HashMap<String, String>,
+        parsed_packages: &ParsedPackages,
+        broken_packages: &Arc<BTreeMap<String, BrokenPackage>>,
+        manifest: &Manifest,
+        save_pb: &ProgressBar,
+    ) -> Result<Option<SavedPlace>> {
+        let output_dir = Path::new(&self.config.output_dir);
+        let file_path = saved_place_path(output_dir, place.id, self.config.compress);
+
+        // A previous run already got this place done, pick up where it left off
+        if self.config.resume
+            && let Ok(Some((path, buffer))) =
+                read_saved_place(output_dir, place.id, self.config.compress).await
+            && !buffer.is_empty()
+        {
+            save_pb.set_message(format!(
+                "Resuming place {} ({}) from {}",
+                place.name,
+                place.id,
+                path.display()
+            ));
+            save_pb.inc(1);
+            return Ok(Some(SavedPlace {
+                id: place.id,
+                name: place.name,
+                path,
+                buffer,
+                replacements: 0,
+                replaced_packages: BTreeSet::new(),
+                resumed: true,
+                instances_before: 0,
+                instances_after: 0,
+                manifest_entry: None,
+            }));
+        }
+
+        // Leave PackageLinks of unselected packages exactly as they are
+        place.to_work.retain(|work| {
+            let selected = self.is_package_selected(&work.package_id_numbers);
+            if !selected {
+                report(
+                    &self.failed_tx,
+                    Failure::skipped(
+                        format!("place {} ({})", place.name, place.id),
+                        format!(
+                            "package {} skipped by package filter",
+                            work.package_id_numbers
+                        ),
+                    ),
+                );
+            }
+            selected
+        });
+
+        // Neither the place nor its packages changed since it was last saved
+        let place_packages: BTreeMap<String, String> = place
+            .to_work
+            .iter()
+            .filter_map(|work| {
+                package_hashes
+                    .get(&work.package_id_numbers)
+                    .map(|hash| (work.package_id_numbers.clone(), hash.clone()))
+            })
+            .collect();
+        if !self.config.force && manifest.is_unchanged(place.id, &place.sha256, &place_packages) {
+            report(
+                &self.failed_tx,
+                Failure::skipped(
+                    format!("place {} ({})", place.name, place.id),
+                    "it and its packages are unchanged since it was last published (use --force to process it anyway)",
+                ),
+            );
+            save_pb.inc(1);
+            return Ok(None);
+        }
+
+        // Nothing would change, so don't rewrite the file or bump its version
+        if place.to_work.is_empty() && !self.config.force_rewrite {
+            report(
+                &self.failed_tx,
+                Failure::skipped(
+                    format!("place {} ({})", place.name, place.id),
+                    "no PackageLinks to replace",
+                ),
+            );
+            save_pb.inc(1);
+            return Ok(None);
+        }
+
+        save_pb.set_message(format!(
+            "Processing replacements for place {} ({})",
+            place.name, place.id
+        ));
+        let (mut place, replaced, buffer, verify_error) = {
+            let parsed_packages = Arc::clone(parsed_packages);
+            let broken_packages = Arc::clone(broken_packages);
+            let config = self.config.clone();
+            let failed_tx = self.failed_tx.clone();
+            let save_pb = save_pb.clone();
+            tokio::task::spawn_blocking(
+                move || -> Result<(PlaceData, Replaced, Vec<u8>, Option<String>)> {
+                    let replaced = replace_packages(
+                        &mut place,
+                        &parsed_packages,
+                        &broken_packages,
+                        &config,
+                        &failed_tx,
+                    );
+
+                    // Every package in this place failed, so publishing it would only bump its version
+                    if replaced.replacements == 0
+                        && !config.publish_unchanged
+                        && !config.force_rewrite
+                    {
+                        return Ok((place, replaced, Vec::new(), None));
+                    }
+
+                    save_pb.set_message(format!(
+                        "Serializing place {} ({}) with {} replacements",
+                        place.name, place.id, replaced.replacements
+                    ));
+                    let mut buffer = Vec::new();
+                    rbx_binary::to_writer(&mut buffer, &place.dom, place.dom.root().children())?;
+
+                    let verify_error = if config.verify_output {
+                        save_pb
+                            .set_message(format!("Verifying place {} ({})", place.name, place.id));
+                        verify_round_trip(&place.dom, &buffer).err()
+                    } else {
+                        None
+                    };
+                    Ok((place, replaced, buffer, verify_error))
+                },
+            )
+            .await??
+        };
+
+        if replaced.replacements == 0
+            && !self.config.publish_unchanged
+            && !self.config.force_rewrite
+        {
+            let failure = if replaced.up_to_date == place.to_work.len() {
+                Failure::skipped(
+                    format!("place {} ({})", place.name, place.id),
+                    "every PackageLink is already up to date (--replace-only-if-newer)",
+                )
+            } else {
+                Failure::warning(
+                    format!("place {} ({})", place.name, place.id),
+                    "no PackageLinks could be replaced, left unchanged and not published (use --publish-unchanged to publish it anyway)",
+                )
+            };
+            report(&self.failed_tx, failure);
+            save_pb.inc(1);
+            return Ok(None);
+        }
+
+        // A corrupt place is worse than an outdated one, so it's never saved or published
+        if let Some(verify_error) = verify_error {
+            report(
+                &self.failed_tx,
+                Failure::error(
+                    format!("place {} ({})", place.name, place.id),
+                    format!(
+                        "failed --verify-output after {} replacement(s), not saved or published: {}",
+                        replaced.replacements, verify_error
+                    ),
+                ),
+            );
+            save_pb.inc(1);
+            return Ok(None);
+        }
+
+        if replaced.removed_broken > 0 {
+            info!(
+                "Removed {} PackageLink(s) of deleted packages from place {} ({})",
+                replaced.removed_broken, place.name, place.id
+            );
+        }
+        if self.config.strip_links {
+            info!(
+                "Stripped {} PackageLink(s) from place {} ({})",
+                replaced.stripped_links, place.name, place.id
+            );
+        }
+
+        save_pb.set_message(format!("Saving to {}", file_path.display()));
+        let written = write_saved_place(&file_path, &buffer, self.config.compress).await?;
+        if self.config.compress {
+            info!(
+                "Saved place {} ({}) compressed to {}, {} uncompressed",
+                place.name,
+                place.id,
+                HumanBytes(written as u64),
+                HumanBytes(buffer.len() as u64)
+            );
+        }
+        // The export is extra, so a failure doesn't stop the place from being published
+        if let Some(export_dir) = &self.config.export_rojo {
+            save_pb.set_message(format!(
+                "Exporting place {} ({}) for Rojo",
+                place.name, place.id
+            ));
+            let export_dir = Path::new(export_dir).join(place.id.to_string());
+            let (place_back, exported) = tokio::task::spawn_blocking(move || {
+                let exported = export_place(&place.dom, &export_dir);
+                (place, exported)
+            })
+            .await?;
+            place = place_back;
+            if let Err(e) = exported {
+                report(
+                    &self.failed_tx,
+                    Failure::warning(
+                        format!("place {} ({})", place.name, place.id),
+                        format!("failed to export for Rojo: {:#}", e),
+                    ),
+                );
+            }
+        }
+
+        let manifest_entry = ManifestEntry {
+            source_sha256: place.sha256.clone(),
+            saved_sha256: hex::encode(Sha256::digest(&buffer)),
+            packages: place_packages,
+        };
+
+        save_pb.inc(1);
+        Ok(Some(SavedPlace {
+            id: place.id,
+            name: place.name,
+            path: file_path,
+            buffer,
+            replacements: replaced.replacements,
+            replaced_packages: replaced.packages,
+            resumed: false,
+            instances_before: replaced.instances_before,
+            instances_after: replaced.instances_after,
+            manifest_entry: Some(manifest_entry),
+        }))
+    }
+
+    /// Saved place files in the output directory whose place is no longer in the
+    /// universe, deleted or moved since an earlier run, for `--prune-output`.
+    /// The whole places list is read for this, `--limit-places` or not, so a
+    /// place that's only left out of this run is never counted as gone.
+    pub async fn stale_saved_places(&self, universe_id: u64) -> Result<Vec<PathBuf>> {
+        if self.config.offline {
+            bail!("--prune-output needs the live places list, it can't be used offline");
+        }
+
+        let (pages_tx, mut pages_rx) = mpsc::channel(self.config.pages_concurrency.max(1));
+        let pages_task = tokio::spawn(fetch_place_pages(
+            Arc::clone(&self.client),
+            self.fetch_options.clone(),
+            universe_id,
+            self.config.sort_order,
+            self.config.page_size,
+            None,
+            pages_tx,
+        ));
+        let mut live_ids: HashSet<u64> = HashSet::new();
+        while let Some(page) = pages_rx.recv().await {
+            live_ids.extend(page.iter().map(|place| *place.id()));
+        }
+        pages_task.await??;
+
+        let output_dir = Path::new(&self.config.output_dir);
+        let mut stale: Vec<PathBuf> = Vec::new();
+        let mut entries = match tokio::fs::read_dir(output_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stale),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", output_dir.display()));
+            }
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if let Some(place_id) = saved_place_id(&path)
+                && !live_ids.contains(&place_id)
+            {
+                stale.push(path);
+            }
+        }
+        stale.sort();
+        Ok(stale)
+    }
+
+    /// Compares every place saved in the output directory with its live version.
+    /// Places that can't be read, downloaded or parsed are reported and left out.
+    #[tracing::instrument(skip_all)]
+    pub async fn diff_saved_places(&self) -> Result<Vec<(u64, PlaceDiff)>> {
+        let output_dir = Path::new(&self.config.output_dir);
+        let mut place_ids: Vec<u64> = Vec::new();
+        let mut entries = tokio::fs::read_dir(output_dir)
+            .await
+            .with_context(|| format!("Failed to read {}", output_dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(place_id) = saved_place_id(&entry.path()) {
+                place_ids.push(place_id);
+            }
+        }
+        // A place can be saved both compressed and not, it's compared once
+        place_ids.sort();
+        place_ids.dedup();
+
+        let diff_pb = progress::add(ProgressBar::new(place_ids.len() as u64));
+        diff_pb.set_style(self.spinner_style.clone());
+        diff_pb.set_prefix("[diff]");
+
+        let mut diffs: Vec<(u64, PlaceDiff)> = Vec::new();
+        for place_id in place_ids {
+            diff_pb.set_message(format!(
+                "Comparing place {} with its live version",
+                place_id
+            ));
+
+            let saved_bytes =
+                match read_saved_place(output_dir, place_id, self.config.compress).await {
+                    Ok(Some((_, b))) => b,
+                    // Removed since the output directory was listed
+                    Ok(None) => {
+                        diff_pb.inc(1);
+                        continue;
+                    }
+                    Err(e) => {
+                        report(
+                            &self.failed_tx,
+                            Failure::error(
+                                format!("place {}", place_id),
+                                format!("failed to read the saved copy: {}", e),
+                            ),
+                        );
+                        diff_pb.inc(1);
+                        continue;
+                    }
+                };
+            let live_bytes = match self.download_place(place_id).await {
+                Ok(b) => b,
+                Err(e) => {
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("place {}", place_id),
+                            format!("failed to download the live version: {}", e),
+                        ),
+                    );
+                    diff_pb.inc(1);
+                    continue;
+                }
+            };
+
+            let diff = tokio::task::spawn_blocking(move || -> Result<PlaceDiff> {
+                let live_dom = rbx_binary::from_reader(Cursor::new(live_bytes))?;
+                let saved_dom = rbx_binary::from_reader(Cursor::new(saved_bytes))?;
+                Ok(diff_doms(&live_dom, &saved_dom))
+            })
+            .await?;
+            match diff {
+                Ok(diff) => diffs.push((place_id, diff)),
+                Err(e) => {
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("place {}", place_id),
+                            format!("failed to parse for diffing: {}", e),
+                        ),
+                    );
+                }
+            }
+            diff_pb.inc(1);
+        }
+
+        diff_pb.finish_and_clear();
+
+        Ok(diffs)
+    }
+
+    // Downloads what's currently live for each place into ./backups. Places that
+    // couldn't be backed up are left out of the returned list so they never get
+    // overwritten without a recoverable copy.
+    #[tracing::instrument(skip_all)]
+    pub async fn backup_live_places(
+        &self,
+        saved_places: Vec<SavedPlace>,
+    ) -> Result<Vec<SavedPlace>> {
+        let backup_pb = progress::add(ProgressBar::new(saved_places.len() as u64));
+        backup_pb.set_style(self.spinner_style.clone());
+        backup_pb.set_prefix("[backup]");
+
+        let folder = Path::new("backups");
+        tokio::fs::create_dir_all(folder).await?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let mut backed_up: Vec<SavedPlace> = Vec::new();
+        for saved in saved_places.into_iter() {
+            backup_pb.set_message(format!(
+                "Backing up live place {} ({})",
+                saved.name, saved.id
+            ));
+
+            let live_bytes = match self.download_place(saved.id).await {
+                Ok(b) => b,
+                Err(e) => {
+                    report(
+                        &self.failed_tx,
+                        Failure::error(
+                            format!("place {} ({})", saved.name, saved.id),
+                            format!("failed to back up, skipping its publish: {}", e),
+                        ),
+                    );
+                    backup_pb.inc(1);
+                    continue;
+                }
+            };
+
+            let file_path = folder.join(format!("{}-{}.rbxl", saved.id, timestamp));
+            if let Err(e) = write_atomically(&file_path, &live_bytes).await {
+                report(
+                    &self.failed_tx,
+                    Failure::error(
+                        format!("place {} ({})", saved.name, saved.id),
+                        format!(
+                            "failed to write backup {}, skipping its publish: {}",
+                            file_path.display(),
+                            e
+                        ),
+                    ),
+                );
+                backup_pb.inc(1);
+                continue;
+            }
+
+            backed_up.push(saved);
+            backup_pb.inc(1);
+        }
+
+        backup_pb.finish_with_message("Backed up live places to ./backups");
+
+        Ok(backed_up)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn publish_saved_places(
+        &self,
+        saved_places: Vec<SavedPlace>,
+        rbxl_api_key: &str,
+        universe_id: u64,
+    ) -> (Vec<PublishResult>, Vec<SavedPlace>) {
+        self.publish_saved_places_with(
+            saved_places,
+            rbxl_api_key,
+            universe_id,
+            None::<fn(&SavedPlace) -> PublishDecision>,
+        )
+        .await
+    }
+
+    /// Same as [`Updater::publish_saved_places`], but asks `decide` about each
+    /// place right before publishing it. Places are then published one at a
+    /// time, so every answer is given with the previous publish finished.
+    /// Skipped places are neither published nor returned as failed.
+    pub async fn publish_saved_places_with(
+        &self,
+        saved_places: Vec<SavedPlace>,
+        rbxl_api_key: &str,
+        universe_id: u64,
+        mut decide: Option<impl FnMut(&SavedPlace) -> PublishDecision>,
+    ) -> (Vec<PublishResult>, Vec<SavedPlace>) {
+        progress::set_phase(Phase::Publish);
+        let publish_pb = progress::add(ProgressBar::new(saved_places.len() as u64));
+        publish_pb.set_style(self.spinner_style.clone());
+        publish_pb.set_prefix("[publish]");
+
+        // A delay only spaces publishes out if they go one after the other
+        let publish_delay = self.config.publish_delay_ms.map(Duration::from_millis);
+        let publish_concurrency = if publish_delay.is_some() || decide.is_some() {
+            1
+        } else {
+            self.config.publish_concurrency.max(1)
+        };
+
+        // The stream only asks for the next place once the one before it is
+        // published, so `decide` is called right before each publish
+        let mut aborted = false;
+        let to_publish = saved_places.into_iter().filter(|saved| {
+            let Some(decide) = decide.as_mut() else {
+                return true;
+            };
+            let skipped = if aborted {
+                "publishing was aborted before this place"
+            } else {
+                match decide(saved) {
+                    PublishDecision::Publish => return true,
+                    PublishDecision::Skip => "not published, skipped when asked",
+                    PublishDecision::Abort => {
+                        aborted = true;
+                        "publishing was aborted at this place"
+                    }
+                }
+            };
+            report(
+                &self.failed_tx,
+                Failure::skipped(format!("place {} ({})", saved.name, saved.id), skipped),
+            );
+            publish_pb.inc(1);
+            false
+        });
+
+        let mut publish_results = futures::stream::iter(to_publish.enumerate().map(|(index, saved)| {
+            let client = Arc::clone(&self.client);
+            let rbxl_api_key = rbxl_api_key.to_string();
+            let publish_pb = publish_pb.clone();
+            let failed_tx = self.failed_tx.clone();
+            let upload_timeout = Duration::from_secs(self.config.download_timeout_secs);
+            let version_type = self.config.version_type;
+            let apis = &self.fetch_options.endpoints.apis;
+            async move {
+                if let Some(publish_delay) = publish_delay
+                    && index > 0
+                {
+                    publish_pb.set_message(format!("Waiting {}ms before the next publish", publish_delay.as_millis()));
+                    tokio::time::sleep(publish_delay).await;
+                }
+                publish_pb.set_message(format!("Publishing place {} ({})", saved.name, saved.id));
+                let publish_response = client
+                    .post(format!(
+                        "{}/universes/v1/{}/places/{}/versions?versionType={}",
+                        apis,
+                        universe_id,
+                        saved.id,
+                        version_type.as_query()
+                    ))
+                    .header("x-api-key", rbxl_api_key)
+                    .header("Content-Type", "application/octet-stream")
+                    .header("Content-Length", saved.buffer.len())
+                    .body(saved.buffer.clone())
+                    .timeout(upload_timeout)
+                    .send()
+                    .await;
+
+                let conflict = matches!(
+                    &publish_response,
+                    Ok(r) if r.status() == StatusCode::CONFLICT || r.status() == StatusCode::PRECONDITION_FAILED
+                );
+                let outcome = match publish_response {
+                    Ok(r) if r.status().is_success() => {
+                        // A success without a version number means nothing actually got published
+                        match r.json::<PublishResponse>().await {
+                            Ok(published) if *published.version_number() > 0 => {
+                                info!(
+                                    "Published place {} ({}) as {} version {}",
+                                    saved.name,
+                                    saved.id,
+                                    version_type.as_query(),
+                                    published.version_number()
+                                );
+                                Ok(*published.version_number())
+                            }
+                            Ok(_) => Err("returned success but no new version number".to_string()),
+                            Err(e) => Err(format!(
+                                "returned success but its response couldn't be read: {}",
+                                e
+                            )),
+                        }
+                    }
+                    Ok(r) if r.status() == StatusCode::CONFLICT || r.status() == StatusCode::PRECONDITION_FAILED => Err(format!(
+                        "HTTP {}, someone else published this place since it was downloaded, so this update is based on a stale version",
+                        r.status()
+                    )),
+                    Ok(r) if r.status() == StatusCode::UNAUTHORIZED || r.status() == StatusCode::FORBIDDEN => Err(format!(
+                        "HTTP {}, the API key isn't allowed to publish this place. It needs universe-place:write on this universe; for a group-owned universe the key has to be created by the group or be given access to its experience",
+                        r.status()
+                    )),
+                    Ok(r) => Err(format!("HTTP {}", r.status())),
+                    Err(e) => Err(e.to_string()),
+                };
+
+                if let Err(reason) = &outcome {
+                    report(&failed_tx, Failure::error(format!("place {} ({})", saved.name, saved.id), format!("failed to publish: {}", reason)));
+                }
+                publish_pb.inc(1);
+
+                let result = PublishResult {
+                    id: saved.id,
+                    name: saved.name.clone(),
+                    outcome,
+                    conflict,
+                };
+                (result, saved)
+            }
+        }))
+        .buffer_unordered(publish_concurrency)
+        .collect::<Vec<(PublishResult, SavedPlace)>>()
+        .await;
+        // Publishes finish in any order, results go by place id
+        publish_results.sort_by_key(|(result, _)| result.id);
+
+        publish_pb.finish_and_clear();
+
+        // Only what actually went live counts as done for the next run
+        if let Err(e) = self.record_published(&publish_results).await {
+            report(
+                &self.failed_tx,
+                Failure::error("manifest", format!("failed to update: {}", e)),
+            );
+        }
+
+        // Failed places keep their buffers so they can be published again
+        let mut results = Vec::new();
+        let mut failed_places = Vec::new();
+        for (result, saved) in publish_results {
+            if result.outcome.is_err() {
+                failed_places.push(saved);
+            }
+            results.push(result);
+        }
+
+        (results, failed_places)
+    }
+
+    async fn record_published(
+        &self,
+        publish_results: &[(PublishResult, SavedPlace)],
+    ) -> Result<()> {
+        let manifest_path = Path::new(&self.config.output_dir).join("manifest.json");
+        let mut manifest = Manifest::load(&manifest_path).await?;
+        for (result, saved) in publish_results {
+            if result.outcome.is_ok()
+                && let Some(manifest_entry) = &saved.manifest_entry
+            {
+                manifest.places.insert(result.id, manifest_entry.clone());
+            }
+        }
+        manifest.save(&manifest_path).await
+    }
+
+    /// Estimates how many bytes fetching these packages would download, without
+    /// downloading them. Returns the total and how many sizes couldn't be found.
+    #[tracing::instrument(skip_all)]
+    pub async fn estimate_package_download_size(
+        &self,
+        package_ids: &[String],
+    ) -> Result<(u64, usize)> {
+        let pinned_versions = if self.config.locked {
+            Lockfile::load(Path::new(&self.config.lockfile))
+                .await?
+                .packages
+        } else {
+            BTreeMap::new()
+        };
+
+        let estimate_pb = progress::add(ProgressBar::new(package_ids.len() as u64));
+        estimate_pb.set_style(self.spinner_style.clone());
+        estimate_pb.set_prefix("[estimate]");
+        estimate_pb.set_message("Looking up package sizes");
+
+        let sizes = futures::stream::iter(
+            package_ids
+                .iter()
+                .filter(|id| self.is_package_selected(id))
+                .map(|package_id| {
+                    let estimate_pb = estimate_pb.clone();
+                    let pinned_version = pinned_versions.get(package_id).copied();
+                    async move {
+                        let size = match self.local_asset_path(
+                            self.config.packages_dir.as_ref(),
+                            package_id,
+                            "rbxm",
+                        ) {
+                            Some(path) => tokio::fs::metadata(path)
+                                .await
+                                .ok()
+                                .map(|metadata| metadata.len()),
+                            None => fetch_asset_size(
+                                &self.client,
+                                package_id,
+                                pinned_version,
+                                &self.fetch_options,
+                            )
+                            .await
+                            .ok()
+                            .flatten(),
+                        };
+                        estimate_pb.inc(1);
+                        size
+                    }
+                }),
+        )
+        .buffer_unordered(self.config.download_concurrency.max(1))
+        .collect::<Vec<Option<u64>>>()
+        .await;
+
+        estimate_pb.finish_and_clear();
+
+        let total = sizes.iter().flatten().sum();
+        let unknown = sizes.iter().filter(|size| size.is_none()).count();
+        Ok((total, unknown))
+    }
+
+    /// Fetches packages the way the lockfile says to, then records the versions
+    /// that were resolved so the next run can reproduce them.
+    pub async fn fetch_locked_package_assets(
+        &self,
+        package_ids: Vec<String>,
+    ) -> Result<FetchedPackages> {
+        // Only honour pinned versions when asked to, otherwise everything moves to latest
+        let lockfile_path = Path::new(&self.config.lockfile);
+        let mut lockfile = Lockfile::load(lockfile_path).await?;
+        let pinned_versions = if self.config.locked {
+            lockfile.packages.clone()
+        } else {
+            BTreeMap::new()
+        };
+
+        let (package_bytes_map, package_stats, broken_packages) = self
+            .fetch_package_assets(package_ids, &pinned_versions)
+            .await;
+
+        let (package_bytes_map, versions) = tokio::task::spawn_blocking(move || {
+            let versions = package_versions(&package_bytes_map);
+            (package_bytes_map, versions)
+        })
+        .await?;
+
+        // Nothing resolved to a new version, leave the lockfile as it is
+        if lockfile.record(versions) {
+            lockfile.save(lockfile_path).await?;
+        }
+
+        Ok((package_bytes_map, package_stats, broken_packages))
+    }
+
+    /// Scans, fetches packages for and saves the places `max_places` at a time,
+    /// dropping each batch's DOMs before the next batch is downloaded. Packages
+    /// are fetched the first time a batch uses them and kept as bytes, which are
+    /// small next to place DOMs, so each batch only parses the ones it uses. The
+    /// most recently used parsed packages are kept between batches too, so ones
+    /// shared by most places aren't parsed again for every batch.
+    #[tracing::instrument(skip(self))]
+    pub async fn update_in_batches(
+        &self,
+        universe_id: u64,
+        max_places: usize,
+    ) -> Result<BatchedUpdate> {
+        let mut update = BatchedUpdate {
+            saved_places: Vec::new(),
+            package_stats: Vec::new(),
+            unfetched_packages: BTreeMap::new(),
+            broken_references: Vec::new(),
+        };
+        let mut broken_packages: BTreeMap<String, BrokenPackage> = BTreeMap::new();
+        let mut package_bytes_map: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut package_hashes: HashMap<String, String> = HashMap::new();
+        // Failed packages too, so they're only tried and reported once
+        let mut attempted: HashSet<String> = HashSet::new();
+        let mut parsed_cache = ParsedPackageCache::new(PARSED_PACKAGE_CACHE_SIZE);
+
+        self.collect_places_in_batches(universe_id, max_places.max(1), async |places_data| {
+            let package_ids = unique_package_ids(&places_data);
+            let new_package_ids: Vec<String> = package_ids
+                .iter()
+                .filter(|id| attempted.insert(id.to_string()))
+                .cloned()
+                .collect();
+            if !new_package_ids.is_empty() {
+                let (fetched, stats, broken) =
+                    self.fetch_locked_package_assets(new_package_ids).await?;
+                broken_packages.extend(broken);
+                package_hashes.extend(
+                    stats
+                        .iter()
+                        .map(|stats| (stats.id.clone(), stats.sha256.clone())),
+                );
+                package_bytes_map.extend(fetched);
+                update.package_stats.extend(stats);
+            }
+
+            update
+                .broken_references
+                .extend(broken_references(&places_data, &broken_packages));
+            for (package_id, places) in missing_packages(&places_data, &package_bytes_map) {
+                if self.is_package_selected(&package_id)
+                    && !broken_packages.contains_key(&package_id)
+                {
+                    *update.unfetched_packages.entry(package_id).or_default() += places;
+                }
+            }
+
+            let batch_packages: HashMap<String, Vec<u8>> = package_ids
+                .into_iter()
+                .filter_map(|id| package_bytes_map.get(&id).map(|bytes| (id, bytes.clone())))
+                .collect();
+            let saved_places = self
+                .process_places(
+                    places_data,
+                    batch_packages,
+                    &package_hashes,
+                    &broken_packages,
+                    Some(&mut parsed_cache),
+                )
+                .await?;
+            update.saved_places.extend(saved_places);
+            Ok(())
+        })
+        .await?;
+
+        Ok(update)
+    }
+
+    /// Runs every phase back to back without asking for confirmation: scan the
+    /// universe, fetch its packages, replace them, save and publish the places.
+    #[tracing::instrument(skip(self, rbxl_api_key))]
+    pub async fn run(&self, universe_id: u64, rbxl_api_key: &str) -> Result<Vec<PublishResult>> {
+        let places_data = self.collect_places_and_package_ids(universe_id).await?;
+
+        let (package_bytes_map, package_stats, broken_packages) = self
+            .fetch_locked_package_assets(unique_package_ids(&places_data))
+            .await?;
+        let package_hashes: HashMap<String, String> = package_stats
+            .into_iter()
+            .map(|stats| (stats.id, stats.sha256))
+            .collect();
+
+        let saved_places = self
+            .process_places_and_save(
+                places_data,
+                package_bytes_map,
+                &package_hashes,
+                &broken_packages,
+            )
+            .await?;
+
+        // Offline runs only ever save the places locally
+        if self.config.offline {
+            return Ok(Vec::new());
+        }
+
+        let saved_places = if self.config.backup {
+            self.backup_live_places(saved_places).await?
+        } else {
+            saved_places
+        };
+
+        let (publish_results, _) = self
+            .publish_saved_places(saved_places, rbxl_api_key, universe_id)
+            .await;
+        Ok(publish_results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rbx_dom_weak::{Instance, InstanceBuilder};
+    use rbx_types::{CFrame, ContentId, Matrix3, Vector3};
+
+    const PACKAGE_ID: &str = "100";
+
+    fn package_link(version: i64) -> InstanceBuilder {
+        InstanceBuilder::new("PackageLink")
+            .with_property(
+                "PackageId",
+                Variant::ContentId(ContentId::from(format!("rbxassetid://{}", PACKAGE_ID))),
+            )
+            .with_property("VersionIdSerialize", Variant::Int64(version))
+    }
+
+    // A place with a copy of the package as `group` in its Workspace
+    fn place_with(group: InstanceBuilder) -> PlaceData {
+        let mut dom = WeakDom::new(InstanceBuilder::new("DataModel"));
+        let workspace = dom.insert(dom.root_ref(), InstanceBuilder::new("Workspace"));
+        let package_link_group = dom.insert(workspace, group);
+        let package_link = dom
+            .get_by_ref(package_link_group)
+            .unwrap()
+            .children()
+            .iter()
+            .copied()
+            .find(|child| dom.get_by_ref(*child).unwrap().class == "PackageLink")
+            .unwrap();
+        PlaceData {
+            id: 1,
+            name: "Place".to_string(),
+            sha256: String::new(),
+            dom,
+            to_work: vec![ToWork {
+                package_id_numbers: PACKAGE_ID.to_string(),
+                package_name: "Package".to_string(),
+                package_link,
+                package_link_group,
+                package_link_parent: workspace,
+            }],
+            other_matches: Vec::new(),
+        }
+    }
+
+    fn replace(place: &mut PlaceData, package: Vec<InstanceBuilder>) -> Replaced {
+        let mut package_dom = WeakDom::new(InstanceBuilder::new("DataModel"));
+        for instance in package {
+            package_dom.insert(package_dom.root_ref(), instance);
+        }
+        let parsed_packages = HashMap::from([(PACKAGE_ID.to_string(), Arc::new(package_dom))]);
+        let (failed_tx, _failed_rx) = mpsc::unbounded_channel();
+        replace_packages(
+            place,
+            &parsed_packages,
+            &BTreeMap::new(),
+            &Config::default(),
+            &failed_tx,
+        )
+    }
+
+    fn workspace_children(place: &PlaceData) -> Vec<&Instance> {
+        let workspace = place.to_work[0].package_link_parent;
+        place
+            .dom
+            .get_by_ref(workspace)
+            .unwrap()
+            .children()
+            .iter()
+            .map(|child| place.dom.get_by_ref(*child).unwrap())
+            .collect()
+    }
+
+    fn child_names<'a>(dom: &'a WeakDom, instance: &Instance) -> Vec<&'a str> {
+        instance
+            .children()
+            .iter()
+            .map(|child| dom.get_by_ref(*child).unwrap().name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn moves_package_under_the_old_copys_parent() {
+        let mut place = place_with(
+            InstanceBuilder::new("Model")
+                .with_name("Package")
+                .with_child(package_link(1))
+                .with_child(InstanceBuilder::new("Part").with_name("OldPart")),
+        );
+        let old_group = place.to_work[0].package_link_group;
+        let old_link = place.to_work[0].package_link;
+
+        let replaced = replace(
+            &mut place,
+            vec![
+                InstanceBuilder::new("Model")
+                    .with_name("Package")
+                    .with_child(package_link(2))
+                    .with_child(InstanceBuilder::new("Part").with_name("NewPart")),
+            ],
+        );
+
+        assert_eq!(replaced.replacements, 1);
+        assert!(place.dom.get_by_ref(old_group).is_none());
+        let children = workspace_children(&place);
+        assert_eq!(children.len(), 1);
+        let root = children[0];
+        assert_ne!(root.referent(), old_group);
+        // The old PackageLink moves over to the new copy
+        assert!(root.children().contains(&old_link));
+        let names = child_names(&place.dom, root);
+        assert!(names.contains(&"NewPart"));
+        assert!(!names.contains(&"OldPart"));
+    }
+
+    #[test]
+    fn moves_every_top_level_instance_of_the_package() {
+        let mut place = place_with(
+            InstanceBuilder::new("Model")
+                .with_name("Package")
+                .with_child(package_link(1)),
+        );
+        let old_group = place.to_work[0].package_link_group;
+
+        replace(
+            &mut place,
+            vec![
+                InstanceBuilder::new("Model")
+                    .with_name("Package")
+                    .with_child(package_link(2)),
+                InstanceBuilder::new("Part").with_name("Extra"),
+                InstanceBuilder::new("Folder").with_name("MoreExtra"),
+            ],
+        );
+
+        assert!(place.dom.get_by_ref(old_group).is_none());
+        let names: Vec<&str> = workspace_children(&place)
+            .iter()
+            .map(|child| child.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Package", "Extra", "MoreExtra"]);
+    }
+
+    #[test]
+    fn keeps_the_placed_copys_name_and_position() {
+        let placed = CFrame::new(Vector3::new(1.0, 2.0, 3.0), Matrix3::identity());
+        let mut place = place_with(
+            InstanceBuilder::new("Part")
+                .with_name("LocalName")
+                .with_property("CFrame", placed)
+                .with_property("Transparency", 0.5f32)
+                .with_child(package_link(1)),
+        );
+
+        replace(
+            &mut place,
+            vec![
+                InstanceBuilder::new("Part")
+                    .with_name("Package")
+                    .with_property(
+                        "CFrame",
+                        CFrame::new(Vector3::new(0.0, 0.0, 0.0), Matrix3::identity()),
+                    )
+                    .with_property("Transparency", 0.0f32)
+                    .with_child(package_link(2)),
+            ],
+        );
+
+        let children = workspace_children(&place);
+        assert_eq!(children.len(), 1);
+        let root = children[0];
+        assert_eq!(root.name, "LocalName");
+        assert_eq!(
+            root.properties.get(&ustr("CFrame")),
+            Some(&Variant::CFrame(placed))
+        );
+        // Anything that isn't placement comes from the new version
+        assert_eq!(
+            root.properties.get(&ustr("Transparency")),
+            Some(&Variant::Float32(0.0))
+        );
+    }
+
+    #[test]
+    fn parsed_package_cache_drops_least_recently_used() {
+        let dom = || Arc::new(WeakDom::new(InstanceBuilder::new("DataModel")));
+        let mut cache = ParsedPackageCache::new(2);
+        cache.insert("a".to_string(), dom());
+        cache.insert("b".to_string(), dom());
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), dom());
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+}
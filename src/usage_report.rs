@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use crate::{
+    PlaceData, SavedPlace,
+    failure_log::{Failure, Severity},
+};
+
+/// How often one place uses one package, taken before the place is processed
+pub struct PackageUsage {
+    pub place_id: u64,
+    pub place_name: String,
+    pub package_id: String,
+    pub occurrences: usize,
+}
+
+#[derive(Serialize)]
+struct UsageRow<'a> {
+    place_id: u64,
+    place_name: &'a str,
+    package_id: &'a str,
+    occurrences: usize,
+    replaced: bool,
+    failure_reason: Option<&'a str>,
+}
+
+/// Every (place, package id) pair in the places, with how many PackageLinks
+/// point at that package
+pub fn package_usage(places_data: &[PlaceData]) -> Vec<PackageUsage> {
+    let mut usage = Vec::new();
+    for place in places_data {
+        let mut occurrences: BTreeMap<&String, usize> = BTreeMap::new();
+        for work in &place.to_work {
+            *occurrences.entry(&work.package_id_numbers).or_default() += 1;
+        }
+        usage.extend(
+            occurrences
+                .into_iter()
+                .map(|(package_id, occurrences)| PackageUsage {
+                    place_id: place.id,
+                    place_name: place.name.clone(),
+                    package_id: package_id.clone(),
+                    occurrences,
+                }),
+        );
+    }
+    usage
+}
+
+/// A place that references a package, in [`CrossUniverseUsage`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PackageUser {
+    pub universe_id: u64,
+    pub place_id: u64,
+    pub place_name: String,
+}
+
+/// Which places of which universes reference each package, for
+/// `--report-unused-packages`. Universes are added one at a time, so only the
+/// package ids are kept and not the places themselves
+#[derive(Debug, Default)]
+pub struct CrossUniverseUsage {
+    pub packages: BTreeMap<String, BTreeSet<PackageUser>>,
+}
+
+impl CrossUniverseUsage {
+    /// Records every package the scanned places of `universe_id` reference. A
+    /// place with several PackageLinks to one package is counted once
+    pub fn add_universe(&mut self, universe_id: u64, places_data: &[PlaceData]) {
+        for place in places_data {
+            for work in &place.to_work {
+                self.packages
+                    .entry(work.package_id_numbers.clone())
+                    .or_default()
+                    .insert(PackageUser {
+                        universe_id,
+                        place_id: place.id,
+                        place_name: place.name.clone(),
+                    });
+            }
+        }
+    }
+
+    /// Packages referenced from more than one of the universes
+    pub fn shared(&self) -> impl Iterator<Item = (&String, &BTreeSet<PackageUser>)> {
+        self.packages.iter().filter(|(_, users)| {
+            let mut universes = users.iter().map(|user| user.universe_id);
+            universes
+                .next()
+                .is_some_and(|first| universes.any(|universe_id| universe_id != first))
+        })
+    }
+
+    /// Packages referenced by a single place in all of the universes
+    pub fn single_place(&self) -> impl Iterator<Item = (&String, &PackageUser)> {
+        self.packages.iter().filter_map(|(package_id, users)| {
+            let mut users = users.iter();
+            match (users.next(), users.next()) {
+                (Some(user), None) => Some((package_id, user)),
+                _ => None,
+            }
+        })
+    }
+}
+
+// Why a package wasn't replaced in a place: what went wrong with the package
+// itself, else what went wrong with the place
+fn failure_reason<'a>(
+    usage: &PackageUsage,
+    failures: &'a [Failure],
+    selected: &impl Fn(&str) -> bool,
+) -> &'a str {
+    if !selected(&usage.package_id) {
+        return "not selected with --packages";
+    }
+
+    let package_context = format!("package {}", usage.package_id);
+    let place_context = format!("place {} ({})", usage.place_name, usage.place_id);
+    let relevant = |failure: &&Failure| failure.level != Severity::Note;
+    failures
+        .iter()
+        .filter(relevant)
+        .find(|failure| failure.context == package_context)
+        .or_else(|| {
+            failures.iter().filter(relevant).find(|failure| {
+                failure.context == place_context && failure.message.contains(&usage.package_id)
+            })
+        })
+        .or_else(|| {
+            failures
+                .iter()
+                .filter(relevant)
+                .find(|failure| failure.context == place_context)
+        })
+        .map(|failure| failure.message.as_str())
+        .unwrap_or("left untouched")
+}
+
+/// Writes one row per (place, package id) pair saying whether the package got
+/// replaced in that place and, when it didn't, the failure that explains why.
+/// `selected` is whether `--packages` let the package be fetched
+pub fn write_usage_csv(
+    path: &Path,
+    usage: &[PackageUsage],
+    saved_places: &[SavedPlace],
+    failures: &[Failure],
+    selected: impl Fn(&str) -> bool,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    // By place then package, so the CSVs of two runs can be diffed
+    let mut sorted: Vec<&PackageUsage> = usage.iter().collect();
+    sorted.sort_by(|a, b| (a.place_id, &a.package_id).cmp(&(b.place_id, &b.package_id)));
+    for usage in sorted {
+        let replaced = saved_places.iter().any(|saved| {
+            saved.id == usage.place_id && saved.replaced_packages.contains(&usage.package_id)
+        });
+        writer.serialize(UsageRow {
+            place_id: usage.place_id,
+            place_name: &usage.place_name,
+            package_id: &usage.package_id,
+            occurrences: usage.occurrences,
+            replaced,
+            failure_reason: (!replaced).then(|| failure_reason(usage, failures, &selected)),
+        })?;
+    }
+
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,1353 @@
+use rbx_dom_weak::{InstanceBuilder, WeakDom, ustr};
+use rbx_types::{Attributes, ContentId, Tags, Variant};
+use roblox_packagelink_updater::{
+    BrokenPackage, Config, PublishDecision, SavedPlace, Updater, broken_references, doctor,
+    endpoints::RobloxEndpoints, failure_log::Failure, output_manifest::write_output_manifest,
+    unique_package_ids, usage_report::CrossUniverseUsage,
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+    path::PathBuf,
+};
+use tokio::sync::mpsc::UnboundedReceiver;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header, method, path, query_param},
+};
+
+const UNIVERSE_ID: u64 = 1;
+const PLACE_ID: u64 = 100;
+const PACKAGE_ID: u64 = 222;
+
+// A Model holding a PackageLink to the package and a single Part
+fn package_model(package_id: u64, part_name: &str, version: i64) -> InstanceBuilder {
+    InstanceBuilder::new("Model")
+        .with_name("Package")
+        .with_child(
+            InstanceBuilder::new("PackageLink")
+                .with_property(
+                    "PackageId",
+                    Variant::ContentId(ContentId::from(format!("rbxassetid://{}", package_id))),
+                )
+                .with_property("VersionIdSerialize", Variant::Int64(version)),
+        )
+        .with_child(InstanceBuilder::new("Part").with_name(part_name))
+}
+
+fn serialize(dom: &WeakDom) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    rbx_binary::to_writer(&mut buffer, dom, dom.root().children()).unwrap();
+    buffer
+}
+
+// A place with an outdated copy of the package in its Workspace
+fn place_bytes() -> Vec<u8> {
+    let dom = WeakDom::new(InstanceBuilder::new("DataModel").with_child(
+        InstanceBuilder::new("Workspace").with_child(package_model(PACKAGE_ID, "OldPart", 1)),
+    ));
+    serialize(&dom)
+}
+
+// The latest version of the package, as assetdelivery would hand it out
+fn package_bytes() -> Vec<u8> {
+    let dom = WeakDom::new(
+        InstanceBuilder::new("DataModel").with_child(package_model(PACKAGE_ID, "NewPart", 2)),
+    );
+    serialize(&dom)
+}
+
+fn asset_metadata(location: String) -> serde_json::Value {
+    json!({
+        "locations": [{ "assetFormat": "source", "location": location, "assetMetadatas": [] }],
+        "requestId": "test",
+        "isArchived": false,
+        "assetTypeId": 9,
+        "isRecordable": true,
+    })
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "roblox-packagelink-updater-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn mock_roblox(server: &MockServer, place: Vec<u8>) {
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/universes/{}/places", UNIVERSE_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "previousPageCursor": null,
+            "nextPageCursor": null,
+            "data": [{ "id": PLACE_ID, "universeId": UNIVERSE_ID, "name": "Lobby", "description": "" }],
+        })))
+        .mount(server)
+        .await;
+
+    for (asset_id, cdn_path, bytes) in [
+        (PLACE_ID, "/cdn/place", place),
+        (PACKAGE_ID, "/cdn/package", package_bytes()),
+    ] {
+        Mock::given(method("GET"))
+            .and(path("/v2/asset/"))
+            .and(query_param("id", asset_id.to_string()))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(asset_metadata(format!(
+                    "{}{}",
+                    server.uri(),
+                    cdn_path
+                ))),
+            )
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(cdn_path))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bytes))
+            .mount(server)
+            .await;
+    }
+}
+
+fn updater(server: &MockServer, output_dir: &std::path::Path, max_retries: u32) -> Updater {
+    updater_with_config(
+        server,
+        Config {
+            max_retries,
+            ..test_config(output_dir)
+        },
+    )
+}
+
+fn test_config(output_dir: &std::path::Path) -> Config {
+    Config {
+        output_dir: output_dir.join("rbxls").display().to_string(),
+        lockfile: output_dir.join("packages.lock").display().to_string(),
+        ..Config::default()
+    }
+}
+
+// An updater pointed at the mock server, along with what it reports
+fn updater_with_failures(
+    server: &MockServer,
+    config: Config,
+    cookie: Option<&secrecy::SecretString>,
+) -> (Updater, UnboundedReceiver<Failure>) {
+    let (failed_tx, failed_rx) = tokio::sync::mpsc::unbounded_channel();
+    let client = Updater::build_client(&config, cookie).unwrap();
+    let updater = Updater::new(client, config, failed_tx).with_endpoints(RobloxEndpoints {
+        develop: server.uri(),
+        assetdelivery: server.uri(),
+        apis: server.uri(),
+        users: server.uri(),
+    });
+    (updater, failed_rx)
+}
+
+fn updater_with_config(server: &MockServer, config: Config) -> Updater {
+    updater_with_failures(server, config, None).0
+}
+
+// Everything the updater reported, once it's done
+async fn failure_messages(
+    updater: Updater,
+    mut failed_rx: UnboundedReceiver<Failure>,
+) -> Vec<String> {
+    drop(updater);
+    let mut messages = Vec::new();
+    while let Some(failure) = failed_rx.recv().await {
+        messages.push(failure.message);
+    }
+    messages
+}
+
+fn part_names(bytes: &[u8]) -> Vec<String> {
+    let dom = rbx_binary::from_reader(Cursor::new(bytes)).unwrap();
+    dom.descendants()
+        .filter(|instance| instance.class == "Part")
+        .map(|instance| instance.name.clone())
+        .collect()
+}
+
+// The children of the first instance named `parent`, as (class, name)
+fn child_names(dom: &WeakDom, parent: &str) -> Vec<(String, String)> {
+    let parent = dom
+        .descendants()
+        .find(|instance| instance.name == parent)
+        .unwrap();
+    parent
+        .children()
+        .iter()
+        .map(|child| {
+            let child = dom.get_by_ref(*child).unwrap();
+            (child.class.to_string(), child.name.clone())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn replaces_package_and_publishes_place() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("publish");
+    let saved_path = output_dir.join("rbxls").join(format!("{}.rbxl", PLACE_ID));
+
+    // Dropping the server checks this was called exactly once
+    Mock::given(method("POST"))
+        .and(path(format!(
+            "/universes/v1/{}/places/{}/versions",
+            UNIVERSE_ID, PLACE_ID
+        )))
+        .and(query_param("versionType", "Published"))
+        .and(header("x-api-key", "test-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "versionNumber": 7 })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let results = updater(&server, &output_dir, 0)
+        .run(UNIVERSE_ID, "test-key")
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id, PLACE_ID);
+    assert_eq!(results[0].outcome, Ok(7));
+
+    let saved = std::fs::read(&saved_path).unwrap();
+    assert_eq!(part_names(&saved), vec!["NewPart".to_string()]);
+
+    let published = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|request| request.method.as_str() == "POST")
+        .unwrap();
+    assert_eq!(published.body, saved);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn retries_truncated_asset_metadata() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    // The first metadata response is cut off partway through, but still a 200
+    Mock::given(method("GET"))
+        .and(path("/v2/asset/"))
+        .and(query_param("id", PACKAGE_ID.to_string()))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"locations": [{"assetFor"#))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("truncated");
+    let (package_bytes_map, _, _) = updater(&server, &output_dir, 1)
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+
+    assert_eq!(
+        package_bytes_map.get(&PACKAGE_ID.to_string()),
+        Some(&package_bytes())
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn falls_back_to_v1_without_source_location() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    // v2 only lists a converted format, v1 still points at the source
+    Mock::given(method("GET"))
+        .and(path("/v2/asset/"))
+        .and(query_param("id", PACKAGE_ID.to_string()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "locations": [{ "assetFormat": "rbxm", "location": format!("{}/cdn/converted", server.uri()), "assetMetadatas": [] }],
+            "requestId": "test",
+            "isArchived": false,
+            "assetTypeId": 9,
+            "isRecordable": true,
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/assetId/{}", PACKAGE_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "location": format!("{}/cdn/package", server.uri()),
+            "requestId": "test",
+        })))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("v1-fallback");
+    let (package_bytes_map, _, _) = updater(&server, &output_dir, 0)
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+
+    assert_eq!(
+        package_bytes_map.get(&PACKAGE_ID.to_string()),
+        Some(&package_bytes())
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn keeps_old_copy_of_malformed_package() {
+    const BROKEN_ID: u64 = 333;
+
+    let server = MockServer::start().await;
+    let place = WeakDom::new(
+        InstanceBuilder::new("DataModel").with_child(
+            InstanceBuilder::new("Workspace")
+                .with_child(package_model(PACKAGE_ID, "OldPart", 1))
+                .with_child(package_model(BROKEN_ID, "BrokenPart", 1)),
+        ),
+    );
+    mock_roblox(&server, serialize(&place)).await;
+
+    // Passes the magic check but doesn't parse
+    Mock::given(method("GET"))
+        .and(path("/v2/asset/"))
+        .and(query_param("id", BROKEN_ID.to_string()))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(asset_metadata(format!("{}/cdn/broken", server.uri()))),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/cdn/broken"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"<roblox!not really".to_vec()))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("malformed");
+    let updater = updater(&server, &output_dir, 0);
+    let places_data = updater
+        .collect_places_and_package_ids(UNIVERSE_ID)
+        .await
+        .unwrap();
+    let (package_bytes_map, package_stats, broken_packages) = updater
+        .fetch_package_assets(unique_package_ids(&places_data), &BTreeMap::new())
+        .await;
+    let package_hashes: HashMap<String, String> = package_stats
+        .into_iter()
+        .map(|stats| (stats.id, stats.sha256))
+        .collect();
+    let saved_places = updater
+        .process_places_and_save(
+            places_data,
+            package_bytes_map,
+            &package_hashes,
+            &broken_packages,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(saved_places.len(), 1);
+    let mut parts = part_names(&saved_places[0].buffer);
+    parts.sort();
+    assert_eq!(parts, vec!["BrokenPart".to_string(), "NewPart".to_string()]);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn skips_package_nested_in_replaced_package() {
+    const INNER_ID: u64 = 333;
+
+    let server = MockServer::start().await;
+    // The inner copy is scanned too, but replacing the outer one first takes it along
+    let place = WeakDom::new(InstanceBuilder::new("DataModel").with_child(
+        InstanceBuilder::new("Workspace").with_child(
+            package_model(PACKAGE_ID, "OldPart", 1).with_child(package_model(
+                INNER_ID,
+                "OldInnerPart",
+                1,
+            )),
+        ),
+    ));
+    mock_roblox(&server, serialize(&place)).await;
+
+    let inner = WeakDom::new(InstanceBuilder::new("DataModel").with_child(package_model(
+        INNER_ID,
+        "NewInnerPart",
+        2,
+    )));
+    Mock::given(method("GET"))
+        .and(path("/v2/asset/"))
+        .and(query_param("id", INNER_ID.to_string()))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(asset_metadata(format!("{}/cdn/inner", server.uri()))),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/cdn/inner"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(serialize(&inner)))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("nested");
+    let (updater, failed_rx) = updater_with_failures(&server, test_config(&output_dir), None);
+    let update = updater.update_in_batches(UNIVERSE_ID, 1).await.unwrap();
+    let messages = failure_messages(updater, failed_rx).await;
+
+    assert_eq!(update.saved_places.len(), 1);
+    assert_eq!(part_names(&update.saved_places[0].buffer), vec!["NewPart"]);
+    assert!(messages.iter().any(|message| message.contains(&format!(
+        "PackageLink for package {} is no longer in the place",
+        INNER_ID
+    ))));
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn updates_places_in_batches() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("batches");
+    let update = updater(&server, &output_dir, 0)
+        .update_in_batches(UNIVERSE_ID, 1)
+        .await
+        .unwrap();
+
+    assert_eq!(update.saved_places.len(), 1);
+    assert_eq!(
+        part_names(&update.saved_places[0].buffer),
+        vec!["NewPart".to_string()]
+    );
+    let saved_dom = rbx_binary::from_reader(Cursor::new(&update.saved_places[0].buffer)).unwrap();
+    assert_eq!(
+        update.saved_places[0].instances_after,
+        saved_dom.descendants().count()
+    );
+    assert_eq!(update.package_stats.len(), 1);
+    assert!(update.unfetched_packages.is_empty());
+
+    let manifest_path = output_dir.join("outputs.json");
+    write_output_manifest(&manifest_path, &update.saved_places)
+        .await
+        .unwrap();
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+    assert_eq!(manifest["places"][0]["id"], PLACE_ID);
+    assert_eq!(
+        manifest["places"][0]["sha256"],
+        hex::encode(Sha256::digest(&update.saved_places[0].buffer))
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn removes_links_to_deleted_packages() {
+    const DELETED_ID: u64 = 444;
+
+    let server = MockServer::start().await;
+    let place = WeakDom::new(
+        InstanceBuilder::new("DataModel").with_child(
+            InstanceBuilder::new("Workspace")
+                .with_child(package_model(PACKAGE_ID, "OldPart", 1))
+                .with_child(package_model(DELETED_ID, "DeletedPart", 1)),
+        ),
+    );
+    mock_roblox(&server, serialize(&place)).await;
+    Mock::given(method("GET"))
+        .and(path("/v2/asset/"))
+        .and(query_param("id", DELETED_ID.to_string()))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("deleted");
+    let updater = updater_with_config(
+        &server,
+        Config {
+            remove_broken: true,
+            ..test_config(&output_dir)
+        },
+    );
+    let places_data = updater
+        .collect_places_and_package_ids(UNIVERSE_ID)
+        .await
+        .unwrap();
+    let (package_bytes_map, package_stats, broken_packages) = updater
+        .fetch_package_assets(unique_package_ids(&places_data), &BTreeMap::new())
+        .await;
+    assert_eq!(
+        broken_packages.get(&DELETED_ID.to_string()),
+        Some(&BrokenPackage::Deleted)
+    );
+
+    let broken = broken_references(&places_data, &broken_packages);
+    assert_eq!(broken.len(), 1);
+    assert_eq!(broken[0].package_id, DELETED_ID.to_string());
+
+    let package_hashes: HashMap<String, String> = package_stats
+        .into_iter()
+        .map(|stats| (stats.id, stats.sha256))
+        .collect();
+    let saved_places = updater
+        .process_places_and_save(
+            places_data,
+            package_bytes_map,
+            &package_hashes,
+            &broken_packages,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(saved_places.len(), 1);
+    assert_eq!(
+        part_names(&saved_places[0].buffer),
+        vec!["NewPart".to_string()]
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn doctor_reports_each_check() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api-keys/v1/introspect"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "enabled": true })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1/users/authenticated"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("doctor");
+    let config = Config {
+        endpoints: RobloxEndpoints {
+            develop: server.uri(),
+            assetdelivery: server.uri(),
+            apis: server.uri(),
+            users: server.uri(),
+        },
+        ..test_config(&output_dir)
+    };
+    let client = Updater::build_plain_client(&config).unwrap();
+    let cookie = "expired".to_string().into();
+    let checks = doctor::run_checks(&client, &config, "test-key", Some(&cookie)).await;
+
+    let failed: Vec<&str> = checks
+        .iter()
+        .filter(|check| !check.passed())
+        .map(|check| check.name.as_str())
+        .collect();
+    assert_eq!(checks.len(), 7);
+    assert_eq!(failed, vec![".ROBLOSECURITY"]);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn aborting_interactive_publish_publishes_nothing() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "versionNumber": 7 })))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("abort-publish");
+    let updater = updater(&server, &output_dir, 0);
+    let update = updater.update_in_batches(UNIVERSE_ID, 1).await.unwrap();
+    let mut asked = Vec::new();
+    let (results, failed_places) = updater
+        .publish_saved_places_with(
+            update.saved_places,
+            "test-key",
+            UNIVERSE_ID,
+            Some(|saved: &SavedPlace| {
+                asked.push(saved.id);
+                PublishDecision::Abort
+            }),
+        )
+        .await;
+
+    assert_eq!(asked, vec![PLACE_ID]);
+    assert!(results.is_empty());
+    assert!(failed_places.is_empty());
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn resumes_from_compressed_places() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("compress");
+    let update = updater_with_config(
+        &server,
+        Config {
+            compress: true,
+            ..test_config(&output_dir)
+        },
+    )
+    .update_in_batches(UNIVERSE_ID, 1)
+    .await
+    .unwrap();
+    let saved_path = output_dir
+        .join("rbxls")
+        .join(format!("{}.rbxl.gz", PLACE_ID));
+    assert!(saved_path.exists());
+
+    // Resuming without --compress still finds the gzipped copy
+    let resumed = updater_with_config(
+        &server,
+        Config {
+            resume: true,
+            ..test_config(&output_dir)
+        },
+    )
+    .update_in_batches(UNIVERSE_ID, 1)
+    .await
+    .unwrap();
+    assert!(resumed.saved_places[0].resumed);
+    assert_eq!(
+        resumed.saved_places[0].buffer,
+        update.saved_places[0].buffer
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn downloads_unparseable_package_again() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    // The first download passes the magic check but doesn't parse
+    Mock::given(method("GET"))
+        .and(path("/cdn/package"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"<roblox!not really".to_vec()))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("package-retries");
+    let (package_bytes_map, package_stats, _) = updater_with_config(
+        &server,
+        Config {
+            max_package_retries: 1,
+            ..test_config(&output_dir)
+        },
+    )
+    .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+    .await;
+
+    assert_eq!(
+        package_bytes_map.get(&PACKAGE_ID.to_string()),
+        Some(&package_bytes())
+    );
+    assert_eq!(package_stats[0].attempts, 2);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn resume_downloads_truncated_cached_package_again() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("package-cache");
+    let fetch = |resume| {
+        updater_with_config(
+            &server,
+            Config {
+                resume,
+                ..test_config(&output_dir)
+            },
+        )
+    };
+    fetch(false)
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+    let cached_path = output_dir
+        .join("rbxls")
+        .join("packages")
+        .join(format!("{}.rbxm", PACKAGE_ID));
+    assert_eq!(std::fs::read(&cached_path).unwrap(), package_bytes());
+
+    // Cut short the way an interrupted download would leave it
+    std::fs::write(&cached_path, &package_bytes()[..16]).unwrap();
+    let (package_bytes_map, package_stats, _) = fetch(true)
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+    assert_eq!(
+        package_bytes_map.get(&PACKAGE_ID.to_string()),
+        Some(&package_bytes())
+    );
+    assert_eq!(package_stats[0].attempts, 1);
+    assert_eq!(std::fs::read(&cached_path).unwrap(), package_bytes());
+
+    // Now whole, so it's reused without downloading
+    let (_, package_stats, _) = fetch(true)
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+    assert_eq!(package_stats[0].attempts, 0);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn exports_updated_places_for_rojo() {
+    let server = MockServer::start().await;
+    let place = WeakDom::new(
+        InstanceBuilder::new("DataModel")
+            .with_child(
+                InstanceBuilder::new("Workspace")
+                    .with_child(package_model(PACKAGE_ID, "OldPart", 1)),
+            )
+            .with_child(
+                InstanceBuilder::new("ServerScriptService").with_child(
+                    InstanceBuilder::new("Script")
+                        .with_name("Main")
+                        .with_property("Source", "print(\"hi\")"),
+                ),
+            ),
+    );
+    mock_roblox(&server, serialize(&place)).await;
+
+    let output_dir = scratch_dir("rojo");
+    let export_dir = output_dir.join("rojo");
+    updater_with_config(
+        &server,
+        Config {
+            export_rojo: Some(export_dir.display().to_string()),
+            ..test_config(&output_dir)
+        },
+    )
+    .update_in_batches(UNIVERSE_ID, 1)
+    .await
+    .unwrap();
+
+    let place_dir = export_dir.join(PLACE_ID.to_string());
+    let model = std::fs::read_to_string(place_dir.join("Workspace").join("Package.rbxmx")).unwrap();
+    assert!(model.contains("NewPart"));
+    assert!(!model.contains("OldPart"));
+    assert_eq!(
+        std::fs::read_to_string(
+            place_dir
+                .join("ServerScriptService")
+                .join("Main.server.lua")
+        )
+        .unwrap(),
+        "print(\"hi\")"
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn sends_cookie_to_develop() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/universes/{}", UNIVERSE_ID)))
+        .and(header("cookie", ".ROBLOSECURITY=secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": UNIVERSE_ID,
+            "name": "Private",
+            "creatorType": "User",
+            "creatorTargetId": 1,
+            "creatorName": "tester",
+        })))
+        .mount(&server)
+        .await;
+
+    // The cookie jar is scoped from the configured endpoints
+    let output_dir = scratch_dir("cookie-scope");
+    let config = Config {
+        endpoints: RobloxEndpoints {
+            develop: server.uri(),
+            assetdelivery: server.uri(),
+            apis: server.uri(),
+            users: server.uri(),
+        },
+        ..test_config(&output_dir)
+    };
+    let cookie = secrecy::SecretString::from("secret".to_string());
+    let (updater, _failed_rx) = updater_with_failures(&server, config, Some(&cookie));
+    let universe = updater.fetch_universe(UNIVERSE_ID).await.unwrap();
+    assert_eq!(universe.name(), "Private");
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn finds_saved_places_no_longer_in_universe() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("prune");
+    let saved_dir = output_dir.join("rbxls");
+    std::fs::create_dir_all(&saved_dir).unwrap();
+    for file in [
+        format!("{}.rbxl", PLACE_ID),
+        "999.rbxl".to_string(),
+        "998.rbxl.gz".to_string(),
+        "manifest.json".to_string(),
+    ] {
+        std::fs::write(saved_dir.join(file), b"").unwrap();
+    }
+
+    let stale = updater_with_config(&server, test_config(&output_dir))
+        .stale_saved_places(UNIVERSE_ID)
+        .await
+        .unwrap();
+    assert_eq!(
+        stale,
+        vec![saved_dir.join("998.rbxl.gz"), saved_dir.join("999.rbxl")]
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn skips_root_place() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/universes/{}", UNIVERSE_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": UNIVERSE_ID,
+            "name": "Test",
+            "creatorType": "User",
+            "creatorTargetId": 1,
+            "creatorName": "tester",
+            "rootPlaceId": PLACE_ID,
+        })))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("root-place");
+    let places_data = updater_with_config(
+        &server,
+        Config {
+            skip_root_place: true,
+            ..test_config(&output_dir)
+        },
+    )
+    .collect_places_and_package_ids(UNIVERSE_ID)
+    .await
+    .unwrap();
+
+    assert!(places_data.is_empty());
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn decompresses_gzipped_downloads() {
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&package_bytes()).unwrap();
+    Mock::given(method("GET"))
+        .and(path("/cdn/package"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-encoding", "gzip")
+                .set_body_bytes(encoder.finish().unwrap()),
+        )
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("gzipped");
+    let (package_bytes_map, _, _) = updater(&server, &output_dir, 0)
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+
+    assert_eq!(
+        package_bytes_map.get(&PACKAGE_ID.to_string()),
+        Some(&package_bytes())
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn reports_publish_conflicts() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(409))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("conflict");
+    let updater = updater_with_config(
+        &server,
+        Config {
+            resume: true,
+            ..test_config(&output_dir)
+        },
+    );
+    let update = updater.update_in_batches(UNIVERSE_ID, 1).await.unwrap();
+    let (results, failed_places) = updater
+        .publish_saved_places(update.saved_places, "test-key", UNIVERSE_ID)
+        .await;
+
+    assert!(results[0].conflict);
+    assert_eq!(failed_places.len(), 1);
+
+    // Updated again from the live version, even with --resume and the stale
+    // copy still saved
+    let updated = updater.update_places_again(failed_places).await.unwrap();
+    assert_eq!(updated.len(), 1);
+    assert!(!updated[0].resumed);
+    assert_eq!(part_names(&updated[0].buffer), vec!["NewPart".to_string()]);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn merges_attributes_and_tags_on_package_root() {
+    let server = MockServer::start().await;
+    let place = WeakDom::new(
+        InstanceBuilder::new("DataModel").with_child(
+            InstanceBuilder::new("Workspace").with_child(
+                package_model(PACKAGE_ID, "OldPart", 1)
+                    .with_property(
+                        "Attributes",
+                        Attributes::new()
+                            .with("Locked", true)
+                            .with("Speed", 10.0f64),
+                    )
+                    .with_property("Tags", Tags::from(vec!["Local".to_string()])),
+            ),
+        ),
+    );
+    mock_roblox(&server, serialize(&place)).await;
+
+    // The new version sets its own attribute value and tag
+    let package = WeakDom::new(
+        InstanceBuilder::new("DataModel").with_child(
+            package_model(PACKAGE_ID, "NewPart", 2)
+                .with_property(
+                    "Attributes",
+                    Attributes::new()
+                        .with("Speed", 20.0f64)
+                        .with("Version", 2.0f64),
+                )
+                .with_property("Tags", Tags::from(vec!["Upstream".to_string()])),
+        ),
+    );
+    Mock::given(method("GET"))
+        .and(path("/cdn/package"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(serialize(&package)))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("attributes");
+    let update = updater(&server, &output_dir, 0)
+        .update_in_batches(UNIVERSE_ID, 1)
+        .await
+        .unwrap();
+
+    let saved = rbx_binary::from_reader(Cursor::new(&update.saved_places[0].buffer)).unwrap();
+    let root = saved
+        .descendants()
+        .find(|instance| instance.class == "Model")
+        .unwrap();
+    let Some(Variant::Attributes(attributes)) = root.properties.get(&ustr("Attributes")) else {
+        panic!("package root lost its attributes");
+    };
+    assert_eq!(attributes.get("Locked"), Some(&Variant::Bool(true)));
+    assert_eq!(attributes.get("Speed"), Some(&Variant::Float64(10.0)));
+    assert_eq!(attributes.get("Version"), Some(&Variant::Float64(2.0)));
+    let Some(Variant::Tags(tags)) = root.properties.get(&ustr("Tags")) else {
+        panic!("package root lost its tags");
+    };
+    assert_eq!(tags.iter().collect::<Vec<_>>(), vec!["Upstream", "Local"]);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn moves_every_top_level_instance_of_package() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    // Everything at the top of the package comes along, not only the first
+    let package = WeakDom::new(
+        InstanceBuilder::new("DataModel")
+            .with_child(package_model(PACKAGE_ID, "NewPart", 2))
+            .with_child(
+                InstanceBuilder::new("Folder")
+                    .with_name("Extras")
+                    .with_child(InstanceBuilder::new("Part").with_name("ExtraPart")),
+            ),
+    );
+    Mock::given(method("GET"))
+        .and(path("/cdn/package"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(serialize(&package)))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("multi-root");
+    let update = updater(&server, &output_dir, 0)
+        .update_in_batches(UNIVERSE_ID, 1)
+        .await
+        .unwrap();
+
+    let saved = rbx_binary::from_reader(Cursor::new(&update.saved_places[0].buffer)).unwrap();
+    assert_eq!(
+        child_names(&saved, "Workspace"),
+        vec![
+            ("Model".to_string(), "Package".to_string()),
+            ("Folder".to_string(), "Extras".to_string()),
+        ]
+    );
+    assert_eq!(
+        part_names(&update.saved_places[0].buffer),
+        vec!["NewPart".to_string(), "ExtraPart".to_string()]
+    );
+    // The old PackageLink now sits on the new copy
+    assert!(
+        child_names(&saved, "Package")
+            .iter()
+            .any(|(class, _)| class == "PackageLink")
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn keeps_local_edits_to_package_copy() {
+    let server = MockServer::start().await;
+    let copy = |root_name: &str, transparency: f32| {
+        package_model(PACKAGE_ID, "Handle", 1)
+            .with_name(root_name)
+            .with_child(
+                InstanceBuilder::new("Part")
+                    .with_name("Trim")
+                    .with_property("Transparency", transparency),
+            )
+    };
+    let place = WeakDom::new(
+        InstanceBuilder::new("DataModel")
+            .with_child(InstanceBuilder::new("Workspace").with_child(copy("LocalName", 0.5))),
+    );
+    mock_roblox(&server, serialize(&place)).await;
+    let package = WeakDom::new(InstanceBuilder::new("DataModel").with_child(copy("Package", 0.0)));
+    Mock::given(method("GET"))
+        .and(path("/cdn/package"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(serialize(&package)))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("local-edits");
+    let update = updater_with_config(
+        &server,
+        Config {
+            keep_descendant_overrides: true,
+            ..test_config(&output_dir)
+        },
+    )
+    .update_in_batches(UNIVERSE_ID, 1)
+    .await
+    .unwrap();
+
+    let saved = rbx_binary::from_reader(Cursor::new(&update.saved_places[0].buffer)).unwrap();
+    assert_eq!(
+        saved
+            .descendants()
+            .filter(|instance| instance.class == "Model")
+            .map(|instance| instance.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["LocalName"]
+    );
+    let trim = saved
+        .descendants()
+        .find(|instance| instance.name == "Trim")
+        .unwrap();
+    assert_eq!(
+        trim.properties.get(&ustr("Transparency")),
+        Some(&Variant::Float32(0.5))
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn reports_packages_shared_across_universes() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("cross-universe");
+    let places_data = updater(&server, &output_dir, 0)
+        .collect_places_and_package_ids(UNIVERSE_ID)
+        .await
+        .unwrap();
+
+    let mut usage = CrossUniverseUsage::default();
+    usage.add_universe(UNIVERSE_ID, &places_data);
+    assert_eq!(usage.shared().count(), 0);
+    assert_eq!(
+        usage
+            .single_place()
+            .map(|(package_id, user)| (package_id.clone(), user.place_id))
+            .collect::<Vec<_>>(),
+        vec![(PACKAGE_ID.to_string(), PLACE_ID)]
+    );
+
+    // The same places seen from a second universe make the package shared
+    usage.add_universe(UNIVERSE_ID + 1, &places_data);
+    assert_eq!(
+        usage
+            .shared()
+            .map(|(package_id, users)| (package_id.clone(), users.len()))
+            .collect::<Vec<_>>(),
+        vec![(PACKAGE_ID.to_string(), 2)]
+    );
+    assert_eq!(usage.single_place().count(), 0);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn skips_package_link_without_package_parent() {
+    let server = MockServer::start().await;
+    // A PackageLink at the top of the file belongs to the DOM root, which has no
+    // parent to put a new copy in
+    let place = WeakDom::new(InstanceBuilder::new("DataModel").with_child(
+        InstanceBuilder::new("PackageLink").with_property(
+            "PackageId",
+            Variant::ContentId(ContentId::from(format!("rbxassetid://{}", PACKAGE_ID))),
+        ),
+    ));
+    mock_roblox(&server, serialize(&place)).await;
+
+    let output_dir = scratch_dir("dangling-parent");
+    let (updater, failed_rx) = updater_with_failures(&server, test_config(&output_dir), None);
+    let places_data = updater
+        .collect_places_and_package_ids(UNIVERSE_ID)
+        .await
+        .unwrap();
+    let messages = failure_messages(updater, failed_rx).await;
+
+    assert!(places_data[0].to_work.is_empty());
+    assert!(
+        messages
+            .iter()
+            .any(|message| message.contains("no package instance with a parent"))
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[test]
+fn orders_failures_by_subject_id() {
+    let mut failures = [
+        Failure::warning("place Lobby (20)", "b"),
+        Failure::warning("package 3", "a"),
+        Failure::warning("place Lobby (20)", "a"),
+        Failure::note("run", "limited"),
+    ];
+    failures.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    assert_eq!(
+        failures.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        vec![
+            "run: limited",
+            "package 3: a",
+            "place Lobby (20): a",
+            "place Lobby (20): b",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn leaves_up_to_date_copies_alone() {
+    let server = MockServer::start().await;
+    // One copy already at the fetched version 2, one still at version 1
+    let place = WeakDom::new(
+        InstanceBuilder::new("DataModel").with_child(
+            InstanceBuilder::new("Workspace")
+                .with_child(package_model(PACKAGE_ID, "CurrentPart", 2))
+                .with_child(package_model(PACKAGE_ID, "OldPart", 1)),
+        ),
+    );
+    mock_roblox(&server, serialize(&place)).await;
+
+    let output_dir = scratch_dir("only-if-newer");
+    let update = updater_with_config(
+        &server,
+        Config {
+            replace_only_if_newer: true,
+            ..test_config(&output_dir)
+        },
+    )
+    .update_in_batches(UNIVERSE_ID, 1)
+    .await
+    .unwrap();
+
+    assert_eq!(update.saved_places[0].replacements, 1);
+    assert_eq!(
+        part_names(&update.saved_places[0].buffer),
+        vec!["CurrentPart".to_string(), "NewPart".to_string()]
+    );
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn takes_upstream_source_of_script_rooted_package() {
+    let server = MockServer::start().await;
+    let module = |name: &str, source: &str, version: i64| {
+        InstanceBuilder::new("ModuleScript")
+            .with_name(name)
+            .with_property("Source", source)
+            .with_child(
+                InstanceBuilder::new("PackageLink")
+                    .with_property(
+                        "PackageId",
+                        Variant::ContentId(ContentId::from(format!("rbxassetid://{}", PACKAGE_ID))),
+                    )
+                    .with_property("VersionIdSerialize", Variant::Int64(version)),
+            )
+    };
+    let place = WeakDom::new(InstanceBuilder::new("DataModel").with_child(
+        InstanceBuilder::new("Workspace").with_child(module("LocalConfig", "return 1", 1)),
+    ));
+    mock_roblox(&server, serialize(&place)).await;
+    let package =
+        WeakDom::new(InstanceBuilder::new("DataModel").with_child(module("Config", "return 2", 2)));
+    Mock::given(method("GET"))
+        .and(path("/cdn/package"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(serialize(&package)))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("script-root");
+    let update = updater(&server, &output_dir, 0)
+        .update_in_batches(UNIVERSE_ID, 1)
+        .await
+        .unwrap();
+
+    let saved = rbx_binary::from_reader(Cursor::new(&update.saved_places[0].buffer)).unwrap();
+    let root = saved
+        .descendants()
+        .find(|instance| instance.class == "ModuleScript")
+        .unwrap();
+    assert_eq!(root.name, "LocalConfig");
+    let source: &[u8] = match root.properties.get(&ustr("Source")) {
+        Some(Variant::String(source)) => source.as_bytes(),
+        Some(Variant::BinaryString(source)) => source.as_ref(),
+        other => panic!("unexpected Source {:?}", other),
+    };
+    assert_eq!(source, b"return 2");
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn writes_lockfile_only_when_versions_change() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    let output_dir = scratch_dir("lockfile");
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let lockfile = output_dir.join("packages.lock");
+    let updater = updater(&server, &output_dir, 0);
+    let places_data = updater
+        .collect_places_and_package_ids(UNIVERSE_ID)
+        .await
+        .unwrap();
+    updater
+        .fetch_locked_package_assets(unique_package_ids(&places_data))
+        .await
+        .unwrap();
+    let written: BTreeMap<String, u64> =
+        serde_json::from_str(&std::fs::read_to_string(&lockfile).unwrap()).unwrap();
+    assert_eq!(written, BTreeMap::from([(PACKAGE_ID.to_string(), 2)]));
+
+    // Same versions in a different layout, so a rewrite would show
+    let unchanged = format!("{{\"{}\":2}}", PACKAGE_ID);
+    std::fs::write(&lockfile, &unchanged).unwrap();
+    updater
+        .fetch_locked_package_assets(unique_package_ids(&places_data))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&lockfile).unwrap(), unchanged);
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+#[tokio::test]
+async fn tries_next_location_when_download_does_not_parse() {
+    let server = MockServer::start().await;
+    mock_roblox(&server, place_bytes()).await;
+
+    // The first location has a truncated copy, the second a good one
+    Mock::given(method("GET"))
+        .and(path("/v2/asset/"))
+        .and(query_param("id", PACKAGE_ID.to_string()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "locations": [
+                { "assetFormat": "source", "location": format!("{}/cdn/truncated", server.uri()), "assetMetadatas": [] },
+                { "assetFormat": "source", "location": format!("{}/cdn/package", server.uri()), "assetMetadatas": [] },
+            ],
+            "requestId": "test",
+            "isArchived": false,
+            "assetTypeId": 9,
+            "isRecordable": true,
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    let mut truncated = package_bytes();
+    truncated.truncate(truncated.len() / 2);
+    Mock::given(method("GET"))
+        .and(path("/cdn/truncated"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(truncated))
+        .mount(&server)
+        .await;
+
+    let output_dir = scratch_dir("truncated-location");
+    let updater = updater(&server, &output_dir, 0);
+    let (package_bytes_map, package_stats, _) = updater
+        .fetch_package_assets(vec![PACKAGE_ID.to_string()], &BTreeMap::new())
+        .await;
+
+    assert_eq!(package_stats.len(), 1);
+    assert_eq!(package_stats[0].location_index, 1);
+    assert_eq!(package_bytes_map[&PACKAGE_ID.to_string()], package_bytes());
+
+    let _ = std::fs::remove_dir_all(&output_dir);
+}